@@ -1,139 +1,930 @@
-pub enum InputFrontend {
-    NoArgs,
-    TwoInts { int1: i32, int2: i32 },
-    Array { array: Vec<i32> },
+use crate::config;
+use crate::cpu::{self, Reg};
+use crate::frontend::{self, parse_i32, parse_int, Frontend, Opt, Registry};
+use crate::isa;
+
+/// Where the program's console input should come from.
+pub enum ProgramInput {
+    File(String),
+    String(String),
+}
+
+/// Initial register or memory contents, set from the command line.
+pub enum Init {
+    Reg {
+        reg: Reg,
+        val: u32,
+    },
+    Mem {
+        addr: u32,
+        val: u32,
+    },
+    /// Load the contents of a file (as big-endian words) at `addr`
+    MemFile {
+        addr: u32,
+        path: String,
+    },
 }
 
 pub struct ParsedArgsFlags {
     pub debug: bool,
+    pub input: Option<ProgramInput>,
+    pub strict_eof: bool,
+    pub output: Option<String>,
+    pub expect_output: Option<String>,
+    pub framebuffer: Option<(u32, u32)>,
+    pub frame_dir: Option<String>,
+    pub timer: bool,
+    pub rng: bool,
+    pub seed: Option<u64>,
+    pub sandbox_dir: Option<String>,
+    pub debug_ports: bool,
+    pub runtime: bool,
+    pub array_address: Option<u32>,
+    pub verbose: bool,
+    /// Instructions the program is allowed to use
+    pub isa: Option<isa::Profile>,
+    pub strict_decoding: bool,
+    /// What to do when signed arithmetic overflows
+    pub overflow: cpu::Overflow,
+    /// Match the messages and register dump of the course's reference VM
+    pub compat: bool,
+    pub max_steps: Option<u64>,
+    pub dump_json: Option<String>,
+    /// `(address, length in words)` ranges of memory to include in the dump
+    pub dump_mem: Vec<(u32, u32)>,
+    /// Rows of memory shown either side of `$30` and the PC in the debugger
+    pub debug_context: Option<u32>,
+    /// Number of past states the debugger keeps for stepping backwards
+    pub debug_history: Option<usize>,
+    /// Initialization to apply in order, along with the option it came from
+    pub inits: Vec<(String, Init)>,
 }
 
 pub struct ParsedArgs {
     pub filename: String,
-    pub frontend: InputFrontend,
+    pub frontend: Box<dyn Frontend>,
     pub load_address: u32,
     pub flags: ParsedArgsFlags,
 }
 
-pub fn parse_args() -> Result<ParsedArgs, String> {
-    let args: Vec<String> = std::env::args().collect();
+/// What `uwmips` was asked to do.
+pub enum Command {
+    /// Run a program (possibly in the debugger)
+    Run(Box<ParsedArgs>),
+    /// Assemble a program (from stdin if there's no input file) into a binary
+    /// (on stdout if there's no output file)
+    Asm {
+        input: Option<String>,
+        output: Option<String>,
+    },
+    /// Print out the instructions in a binary
+    Disasm {
+        filename: String,
+        load_address: u32,
+        isa: Option<isa::Profile>,
+        strict_decoding: bool,
+    },
+    /// Run the test cases in a spec file, with up to `jobs` running at once
+    Test { spec: String, jobs: Option<usize> },
+    /// Print the given help text, and exit
+    Help(String),
+}
 
-    let mut arg = 1;
+/// A malformed command line.
+pub struct ArgsError {
+    pub message: String,
+    /// The subcommand being parsed, if it got that far
+    pub command: Option<&'static str>,
+}
 
-    // Consume flags
-    let mut flags = ParsedArgsFlags { debug: false };
-    loop {
-        match args.get(arg) {
-            Some(s) => match s.as_ref() {
-                "--debug" => {
-                    flags.debug = true;
-                    arg += 1;
-                }
-                _ => break,
-            },
-            None => return Err("Not enough arguments".to_string()),
+impl ArgsError {
+    /// Where to look for help on using the subcommand
+    pub fn hint(&self) -> String {
+        match self.command {
+            Some(command) => format!("Run `{} {} --help` for usage", exec_name(), command),
+            None => format!("Run `{} --help` for usage", exec_name()),
         }
     }
+}
+
+struct Subcommand {
+    name: &'static str,
+    about: &'static str,
+    /// Positional arguments, as shown in the usage line
+    usage: &'static str,
+    options: &'static [Opt],
+}
+
+const fn opt(name: &'static str, value: Option<&'static str>, help: &'static str) -> Opt {
+    Opt { name, value, help }
+}
+
+#[rustfmt::skip]
+const RUN_OPTIONS: &[Opt] = &[
+    opt("frontend",      Some("name"), "Frontend to use, if one isn't given before the filename"),
+    opt("load-address",  Some("addr"), "Address to load the program at (default 0)"),
+    opt("debug",         None,         "Launch an interactive debugger"),
+    opt("input",         Some("file"), "Read program input from a file"),
+    opt("input-string",  Some("str"),  "Read program input from a string"),
+    opt("strict-eof",    None,         "Treat reading past the end of input as an error"),
+    opt("output",        Some("file"), "Write program output to a file"),
+    opt("expect-output", Some("file"), "Check program output against a file"),
+    opt("debug-ports",   None,         "Enable the print and assert ports"),
+    opt("runtime",       None,         "Provide print, init, new and delete natively"),
+    opt("framebuffer",   Some("WxH"),  "Add a memory-mapped framebuffer"),
+    opt("frame-dir",     Some("dir"),  "Directory to dump framebuffer frames into"),
+    opt("timer",         None,         "Add a memory-mapped cycle counter and timer"),
+    opt("rng",           None,         "Add a memory-mapped random number generator"),
+    opt("seed",          Some("n"),    "Seed for the random number generator (implies --rng)"),
+    opt("sandbox-dir",   Some("dir"),  "Give the program access to files in a directory"),
+    opt("array-address", Some("addr"), "Address to place the array or strings at (default: after the program)"),
+    opt("verbose",       None,         "Print out where everything was loaded in memory"),
+    opt("isa",           Some("profile"), "Only allow instructions from a profile (all, cs241) or list"),
+    opt("strict-decoding", None,       "Reject instructions whose unused fields aren't zero"),
+    opt("overflow",      Some("mode"), "On signed overflow: wrap (default), warn or error"),
+    opt("compat",        None,         "Print messages and registers exactly like the course's VM"),
+    opt("reg",           Some("$N=val"),    "Set a register before running (repeatable)"),
+    opt("mem",           Some("addr=val"),  "Set a word of memory before running (repeatable)"),
+    opt("mem-file",      Some("addr:file"), "Load a file into memory before running (repeatable)"),
+    opt("max-steps",     Some("n"),    "Stop the program after running this many instructions"),
+    opt("dump-json",     Some("file"), "Write the final machine state to a file as JSON"),
+    opt("dump-mem",      Some("addr:words"), "Include some memory in --dump-json (repeatable)"),
+    opt("debug-context", Some("n"),    "Rows of memory the debugger shows around $30 and the PC (default 6)"),
+    opt("debug-history", Some("n"),    "Number of steps the debugger can step backwards (default: unlimited)"),
+    opt("no-config",     None,         "Ignore uwmips.toml"),
+];
+
+/// Options which can be given more than once, and so take arrays in the config
+const REPEATABLE: &[&str] = &["reg", "mem", "mem-file", "dump-mem"];
+
+/// Keys allowed in each `[section]` of the config, and the options they set.
+/// Any option (other than --no-config) can also be set outside a section.
+#[rustfmt::skip]
+const CONFIG_SECTIONS: &[(&str, &[(&str, &str)])] = &[
+    ("devices", &[
+        ("framebuffer", "framebuffer"),
+        ("frame-dir",   "frame-dir"),
+        ("timer",       "timer"),
+        ("rng",         "rng"),
+        ("seed",        "seed"),
+        ("sandbox-dir", "sandbox-dir"),
+        ("debug-ports", "debug-ports"),
+        ("runtime",     "runtime"),
+    ]),
+    ("debugger", &[
+        ("context", "debug-context"),
+        ("history", "debug-history"),
+    ]),
+];
+
+#[rustfmt::skip]
+const ASM_OPTIONS: &[Opt] = &[
+    opt("output", Some("file"), "Write the binary to a file instead of stdout"),
+];
+
+#[rustfmt::skip]
+const DISASM_OPTIONS: &[Opt] = &[
+    opt("load-address", Some("addr"), "Address the program is loaded at (default 0)"),
+    opt("isa",          Some("profile"), "Report instructions which aren't in a profile (all, cs241) or list"),
+    opt("strict-decoding", None,         "Show instructions whose unused fields aren't zero as .word"),
+];
 
-    // Consume frontend
-    if args.get(arg).is_none() {
-        return Err("No frontend specified".to_string());
+#[rustfmt::skip]
+const TEST_OPTIONS: &[Opt] = &[
+    opt("jobs", Some("n"), "Number of cases to run at once (default: one per CPU)"),
+];
+
+#[rustfmt::skip]
+const SUBCOMMANDS: &[Subcommand] = &[
+    Subcommand {
+        name: "run",
+        about: "Run a program",
+        usage: "[frontend] <filename> [...args]",
+        options: RUN_OPTIONS,
+    },
+    Subcommand {
+        name: "debug",
+        about: "Run a program in the interactive debugger",
+        usage: "[frontend] <filename> [...args]",
+        options: RUN_OPTIONS,
+    },
+    Subcommand {
+        name: "asm",
+        about: "Assemble a program (read from stdin if no file is given)",
+        usage: "[filename]",
+        options: ASM_OPTIONS,
+    },
+    Subcommand {
+        name: "disasm",
+        about: "Disassemble a program",
+        usage: "<filename>",
+        options: DISASM_OPTIONS,
+    },
+    Subcommand {
+        name: "test",
+        about: "Run the test cases in a spec file",
+        usage: "<spec-file>",
+        options: TEST_OPTIONS,
+    },
+];
+
+fn exec_name() -> String {
+    std::env::args()
+        .next()
+        .unwrap_or_else(|| "uwmips".to_string())
+}
+
+fn top_level_help() -> String {
+    let exec_name = exec_name();
+    let mut help = String::new();
+    help += "A simulator for the MIPS instruction set used in CS241 and CS230\n\n";
+    help += &format!("Usage: {} <command> [OPTIONS] ...\n", exec_name);
+    help += &format!(
+        "       {} [OPTIONS] [frontend] <filename> [...args]   (same as `run`)\n\n",
+        exec_name
+    );
+    help += "Commands:\n";
+    for cmd in SUBCOMMANDS {
+        help += &format!("  {:<8} {}\n", cmd.name, cmd.about);
     }
-    let frontend = &args[arg];
-    arg += 1;
+    help += &format!("  {:<8} {}\n", "help", "Print help for a command");
+    help += &format!(
+        "\nRun `{} <command> --help` for more information on a command.",
+        exec_name
+    );
+    help
+}
 
-    // Consume Filename
-    if args.get(arg).is_none() {
-        return Err("No filename specified".to_string());
+/// Options accepted by a subcommand, including those of any frontends
+fn options(cmd: &Subcommand, frontends: &Registry) -> Vec<&'static Opt> {
+    let mut options: Vec<&'static Opt> = cmd.options.iter().collect();
+    if cmd.name == "run" || cmd.name == "debug" {
+        for f in frontends.all() {
+            options.extend(f.options());
+        }
     }
-    let filename = args[arg].clone();
-    arg += 1;
+    options
+}
 
-    // Per-frontend matching
-    let frontend = match frontend.as_ref() {
-        "noargs" => InputFrontend::NoArgs,
-        "twoints" => {
-            let mut ints: [i32; 2] = [0; 2];
+/// Options accepted by `run`, including those of any frontends
+pub fn run_options(frontends: &Registry) -> Vec<&'static Opt> {
+    options(&SUBCOMMANDS[0], frontends)
+}
 
-            for i in 0..2 {
-                eprint!("Enter value for register {}: ", i + 1);
-                let mut buf = String::new();
-                std::io::stdin()
-                    .read_line(&mut buf)
-                    .map_err(|_| format!("Failed to read register {} value", i + 1))?;
+fn subcommand_help(cmd: &Subcommand, frontends: &Registry) -> String {
+    let mut help = String::new();
+    help += &format!("{}\n\n", cmd.about);
+    help += &format!(
+        "Usage: {} {} [OPTIONS] {}\n\n",
+        exec_name(),
+        cmd.name,
+        cmd.usage
+    );
+    help += "Options:\n";
+    let options = options(cmd, frontends)
+        .into_iter()
+        // `debug` always launches the debugger
+        .filter(|o| !(cmd.name == "debug" && o.name == "debug"));
+    for o in options {
+        let flag = match o.value {
+            Some(value) => format!("--{} <{}>", o.name, value),
+            None => format!("--{}", o.name),
+        };
+        if flag.len() > 22 {
+            help += &format!("  {}\n  {:<22}  {}\n", flag, "", o.help);
+        } else {
+            help += &format!("  {:<22}  {}\n", flag, o.help);
+        }
+    }
+    help += &format!("  {:<22}  {}\n", "-h, --help", "Print this help");
 
-                ints[i] = buf[..buf.len() - 1]
-                    .parse()
-                    .map_err(|_| format!("Failed to parse register {} value", i + 1))?;
-            }
+    if cmd.name == "run" || cmd.name == "debug" {
+        help += "\nFrontends:\n";
+        for f in frontends.all() {
+            help += &format!("  {:<12} {}\n", f.name(), f.usage());
+        }
+    }
+    help.pop();
+    help
+}
 
-            InputFrontend::TwoInts {
-                int1: ints[0],
-                int2: ints[1],
-            }
+/// Parse a word-aligned address.
+pub fn parse_addr(s: &str, what: &str) -> Result<u32, String> {
+    let addr = match parse_int(s) {
+        Some(n) if (0..=u32::MAX as i64).contains(&n) => n as u32,
+        _ => return Err(format!("{} \"{}\" isn't a valid address", what, s)),
+    };
+    if addr % 4 != 0 {
+        return Err(format!("{} 0x{:x} must be word aligned", what, addr));
+    }
+    Ok(addr)
+}
+
+/// Parse a `$N=val` register assignment. `N` may also be `pc`, `hi` or `lo`.
+/// Register numbers aren't range checked here, that's left to `CPU::set_reg`.
+pub fn parse_reg_assignment(s: &str, option: &str) -> Result<(Reg, u32), String> {
+    let (reg, val) = s
+        .split_once('=')
+        .ok_or_else(|| format!("{} \"{}\" should be $N=val", option, s))?;
+    let name = reg.trim();
+    let reg = match name.trim_start_matches('$').to_ascii_lowercase().as_str() {
+        "pc" => Reg::PC,
+        "hi" => Reg::Hi,
+        "lo" => Reg::Lo,
+        n => Reg::Reg(
+            n.parse()
+                .map_err(|_| format!("\"{}\" isn't a register", name))?,
+        ),
+    };
+    Ok((reg, parse_i32(val, "Register value")? as u32))
+}
+
+/// Parse a `WIDTHxHEIGHT` size
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let err = || format!("Invalid size \"{}\", expected WIDTHxHEIGHT", s);
+    let (w, h) = s.split_once('x').ok_or_else(err)?;
+    let w: u32 = w.parse().map_err(|_| err())?;
+    let h: u32 = h.parse().map_err(|_| err())?;
+    // keep the pixels below the memory-mapped registers at 0xffff0000
+    if w == 0 || h == 0 || (w as u64) * (h as u64) > 0x07ffc000 {
+        return Err(err());
+    }
+    Ok((w, h))
+}
+
+fn parse_overflow(s: &str) -> Result<cpu::Overflow, String> {
+    match s {
+        "wrap" => Ok(cpu::Overflow::Wrap),
+        "warn" => Ok(cpu::Overflow::Warn),
+        "error" => Ok(cpu::Overflow::Error),
+        _ => Err(format!(
+            "Invalid overflow mode \"{}\", expected wrap, warn or error",
+            s
+        )),
+    }
+}
+
+/// A command line split up into options and positional arguments.
+#[derive(Default)]
+pub struct CommandLine {
+    pub options: Vec<(&'static str, Option<String>)>,
+    pub positionals: Vec<String>,
+    pub help: bool,
+}
+
+fn split_command_line(options: &[&'static Opt], args: &[String]) -> Result<CommandLine, String> {
+    let mut cl = CommandLine {
+        options: Vec::new(),
+        positionals: Vec::new(),
+        help: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            cl.positionals.extend(args.cloned());
+            break;
         }
-        "twointsargs" => {
-            if args.get(arg).is_none() {
-                return Err("int1 not specified".to_string());
-            }
-            if args.get(arg + 1).is_none() {
-                return Err("int2 not specified".to_string());
+        if arg == "-h" || arg == "--help" {
+            cl.help = true;
+            continue;
+        }
+        let name = match arg.strip_prefix("--") {
+            Some(name) => name,
+            // includes negative numbers
+            None => {
+                cl.positionals.push(arg.clone());
+                continue;
             }
+        };
 
-            arg += 2;
+        let (name, inline_value) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (name, None),
+        };
+        let o = options
+            .iter()
+            .find(|o| o.name == name)
+            .ok_or_else(|| format!("Unknown option --{}", name))?;
+        let value = match (o.value, inline_value) {
+            (Some(_), Some(value)) => Some(value),
+            (Some(value), None) => Some(
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("--{} requires a <{}>", o.name, value))?,
+            ),
+            // so flags turned on in uwmips.toml can be turned off again
+            (None, Some(value)) => match value.as_str() {
+                "true" => None,
+                "false" => Some(value),
+                _ => return Err(format!("--{} should be true or false", o.name)),
+            },
+            (None, None) => None,
+        };
+        cl.options.push((o.name, value));
+    }
+
+    Ok(cl)
+}
 
-            InputFrontend::TwoInts {
-                int1: args[arg - 2].parse().map_err(|_| "Failed to parse int1")?,
-                int2: args[arg - 1].parse().map_err(|_| "Failed to parse int2")?,
+/// An option set in a config file, along with the line it was set on
+pub type ConfigOption = (usize, &'static str, Option<String>);
+
+/// Turn a `key = value` setting in a config file into the `run` option `name`
+/// (given once per array element for repeatable options).
+pub fn config_option(
+    options: &[&'static Opt],
+    name: &str,
+    key: &str,
+    value: config::Value,
+) -> Result<Vec<(&'static str, Option<String>)>, String> {
+    let o = options
+        .iter()
+        .find(|o| o.name == name && o.name != "no-config")
+        .ok_or_else(|| format!("unknown key \"{}\"", key))?;
+
+    match (o.value, value) {
+        (None, config::Value::Bool(true)) => Ok(vec![(o.name, None)]),
+        (None, config::Value::Bool(false)) => Ok(vec![(o.name, Some("false".to_string()))]),
+        (None, _) => Err(format!("{} should be true or false", key)),
+        (Some(value), config::Value::Bool(_)) => Err(format!("{} should be a <{}>", key, value)),
+        (Some(_), config::Value::Array(items)) if REPEATABLE.contains(&o.name) => Ok(items
+            .into_iter()
+            .map(|item| (o.name, Some(item.to_string())))
+            .collect()),
+        (Some(_), config::Value::Array(_)) => Err(format!("{} can't be given more than once", key)),
+        (Some(_), value) => Ok(vec![(o.name, Some(value.to_string()))]),
+    }
+}
+
+/// Read `uwmips.toml` from the working directory (if there is one), turning
+/// its settings into options for `run` / `debug`.
+fn read_config(options: &[&'static Opt]) -> Result<Vec<ConfigOption>, String> {
+    let src = match std::fs::read_to_string(config::FILENAME) {
+        Ok(src) => src,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", config::FILENAME, e)),
+    };
+
+    let mut config_options = Vec::new();
+    let entries = config::parse(&src).map_err(|e| format!("{} {}", config::FILENAME, e))?;
+    for entry in entries {
+        let line = entry.line;
+        let err = |message: String| in_config(config::FILENAME, Some(line), message);
+
+        let name = match &entry.section {
+            None => entry.key.as_str(),
+            Some(section) => {
+                let (_, keys) = CONFIG_SECTIONS
+                    .iter()
+                    .find(|(name, _)| name == section)
+                    .ok_or_else(|| err(format!("unknown section [{}]", section)))?;
+                keys.iter()
+                    .find(|(key, _)| *key == entry.key)
+                    .map(|(_, name)| *name)
+                    .ok_or_else(|| err(format!("unknown key \"{}\" in [{}]", entry.key, section)))?
             }
+        };
+        for (name, value) in config_option(options, name, &entry.key, entry.value).map_err(err)? {
+            config_options.push((line, name, value));
         }
-        "array" => {
-            let mut array = Vec::new();
-
-            eprint!("Enter length of array: ");
-            let mut buf = String::new();
-            std::io::stdin()
-                .read_line(&mut buf)
-                .map_err(|_| "Failed to read length of array")?;
-            let array_len: usize = buf[..buf.len() - 1]
-                .parse()
-                .map_err(|_| "Failed to parse length of array")?;
-
-            for i in 0..array_len {
-                eprint!("Enter array element {}: ", i);
-                let mut buf = String::new();
-                std::io::stdin()
-                    .read_line(&mut buf)
-                    .map_err(|_| "Failed to read array element")?;
-
-                array.push(
-                    buf[..buf.len() - 1]
-                        .parse()
-                        .map_err(|_| "Failed to parse array element")?,
-                );
-            }
+    }
+    Ok(config_options)
+}
 
-            InputFrontend::Array { array }
+/// Point errors in options set by a config file at the line they came from
+pub fn in_config(file: &str, line: Option<usize>, message: String) -> String {
+    match line {
+        Some(line) => format!("{} {}", file, config::Error { line, message }),
+        None => message,
+    }
+}
+
+/// Apply one of the `run` / `debug` options to `args`.
+/// Returns `false` if the option isn't one of them (i.e: it's a frontend's).
+fn apply_run_option(args: &mut RunArgs, name: &str, value: Option<String>) -> Result<bool, String> {
+    let flags = &mut args.flags;
+    // every option which takes a value is given one by split_command_line
+    let value = value.unwrap_or_default();
+    match name {
+        "frontend" => args.frontend = Some(value),
+        "load-address" => args.load_address = Some(parse_addr(&value, "Load address")?),
+        "debug" => flags.debug = true,
+        "input" => flags.input = Some(ProgramInput::File(value)),
+        "input-string" => flags.input = Some(ProgramInput::String(value)),
+        "strict-eof" => flags.strict_eof = true,
+        "output" => flags.output = Some(value),
+        "expect-output" => flags.expect_output = Some(value),
+        "debug-ports" => flags.debug_ports = true,
+        "runtime" => flags.runtime = true,
+        "framebuffer" => flags.framebuffer = Some(parse_size(&value)?),
+        "frame-dir" => flags.frame_dir = Some(value),
+        "timer" => flags.timer = true,
+        "rng" => flags.rng = true,
+        "seed" => {
+            flags.rng = true;
+            flags.seed = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("Seed \"{}\" isn't a number", value))?,
+            );
+        }
+        "sandbox-dir" => flags.sandbox_dir = Some(value),
+        "array-address" => flags.array_address = Some(parse_addr(&value, "Array address")?),
+        "verbose" => flags.verbose = true,
+        "isa" => flags.isa = Some(isa::Profile::parse(&value)?),
+        "strict-decoding" => flags.strict_decoding = true,
+        "overflow" => flags.overflow = parse_overflow(&value)?,
+        "compat" => flags.compat = true,
+        "reg" => {
+            let (reg, val) = parse_reg_assignment(&value, "--reg")?;
+            let init = Init::Reg { reg, val };
+            flags.inits.push((format!("--reg {}", value), init));
+        }
+        "mem" => {
+            let (addr, val) = value
+                .split_once('=')
+                .ok_or_else(|| format!("--mem \"{}\" should be addr=val", value))?;
+            let init = Init::Mem {
+                addr: parse_addr(addr, "Memory address")?,
+                val: parse_i32(val, "Memory value")? as u32,
+            };
+            flags.inits.push((format!("--mem {}", value), init));
+        }
+        "mem-file" => {
+            let (addr, path) = value
+                .split_once(':')
+                .ok_or_else(|| format!("--mem-file \"{}\" should be addr:file", value))?;
+            let init = Init::MemFile {
+                addr: parse_addr(addr, "Memory address")?,
+                path: path.to_string(),
+            };
+            flags.inits.push((format!("--mem-file {}", value), init));
+        }
+        "max-steps" => {
+            flags.max_steps = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("Step limit \"{}\" isn't a number", value))?,
+            );
+        }
+        "dump-json" => flags.dump_json = Some(value),
+        "dump-mem" => {
+            let (addr, len) = value
+                .split_once(':')
+                .ok_or_else(|| format!("--dump-mem \"{}\" should be addr:words", value))?;
+            let addr = parse_addr(addr, "Memory address")?;
+            let len = parse_int(len)
+                .filter(|&n| {
+                    let end = n.checked_mul(4).and_then(|n| n.checked_add(addr as i64));
+                    n >= 0 && matches!(end, Some(end) if end <= 1 << 32)
+                })
+                .ok_or_else(|| format!("--dump-mem \"{}\" doesn't fit in memory", value))?;
+            flags.dump_mem.push((addr, len as u32));
         }
-        _ => return Err("Invalid mode".to_string()),
+        "debug-context" => {
+            flags.debug_context = Some(
+                value
+                    .parse()
+                    .ok()
+                    .filter(|&n| n <= 1024)
+                    .ok_or_else(|| format!("Debugger context \"{}\" isn't valid", value))?,
+            );
+        }
+        "debug-history" => {
+            flags.debug_history = Some(
+                value
+                    .parse()
+                    .map_err(|_| format!("Debugger history \"{}\" isn't a number", value))?,
+            );
+        }
+        "no-config" => {}
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// `run` / `debug` options, as they're being parsed
+struct RunArgs {
+    frontend: Option<String>,
+    load_address: Option<u32>,
+    flags: ParsedArgsFlags,
+}
+
+/// Parse the arguments to `run` / `debug`, after applying the options set in
+/// `config_file`. Frontends can only prompt for their arguments if
+/// `interactive` is set.
+pub fn parse_run_args(
+    config_file: &str,
+    config: Vec<ConfigOption>,
+    cl: CommandLine,
+    debug: bool,
+    interactive: bool,
+    frontends: &Registry,
+) -> Result<ParsedArgs, String> {
+    let mut args = RunArgs {
+        frontend: None,
+        load_address: None,
+        flags: ParsedArgsFlags {
+            debug,
+            input: None,
+            strict_eof: false,
+            output: None,
+            expect_output: None,
+            framebuffer: None,
+            frame_dir: None,
+            timer: false,
+            rng: false,
+            seed: None,
+            sandbox_dir: None,
+            debug_ports: false,
+            runtime: false,
+            array_address: None,
+            verbose: false,
+            isa: None,
+            strict_decoding: false,
+            overflow: cpu::Overflow::Wrap,
+            compat: false,
+            max_steps: None,
+            dump_json: None,
+            dump_mem: Vec::new(),
+            debug_context: None,
+            debug_history: None,
+            inits: Vec::new(),
+        },
     };
+    // options belonging to frontends, which are handled once we know which
+    // frontend is being used
+    let mut frontend_options = Vec::new();
+    // the line of the config file --frontend was set on, if it was
+    let mut frontend_line = None;
+    // same for --load-address
+    let mut load_address_line = None;
 
-    // check for load_address
-    let load_address = match args.get(arg) {
-        Some(addr) => addr
-            .parse()
-            .map_err(|_| "Failed to parse load load_address")?,
-        None => 0,
+    let CommandLine {
+        options,
+        positionals,
+        ..
+    } = cl;
+    // Options given on the command line come last, so they override the config
+    let options = config
+        .into_iter()
+        .map(|(line, name, value)| (Some(line), name, value))
+        .chain(options.into_iter().map(|(name, value)| (None, name, value)));
+    // turning a flag off undoes any earlier settings of it
+    let all_options = run_options(frontends);
+    let is_flag = |name| {
+        all_options
+            .iter()
+            .any(|o| o.name == name && o.value.is_none())
     };
+    let mut set = Vec::new();
+    for (line, name, value) in options {
+        match value.as_deref() {
+            Some("false") if is_flag(name) => set.retain(|&(_, n, _)| n != name),
+            _ => set.push((line, name, value)),
+        }
+    }
+    for (line, name, value) in set {
+        match name {
+            "frontend" => frontend_line = line,
+            "load-address" => load_address_line = line,
+            _ => {}
+        }
+        if !apply_run_option(&mut args, name, value.clone())
+            .map_err(|e| in_config(config_file, line, e))?
+        {
+            frontend_options.push((line, name, value));
+        }
+    }
+    let RunArgs {
+        frontend: frontend_name,
+        mut load_address,
+        flags,
+    } = args;
+    if flags.dump_json.is_some() && flags.debug {
+        return Err("--dump-json can't be used with the debugger".to_string());
+    }
+    if flags.compat && flags.debug {
+        return Err("--compat can't be used with the debugger".to_string());
+    }
+    if !flags.dump_mem.is_empty() && flags.dump_json.is_none() {
+        return Err("--dump-mem needs --dump-json".to_string());
+    }
+
+    // The frontend is optional, so only treat the first argument as one if
+    // there's a filename after it. Without one, it's the one given by
+    // --frontend, or else options belonging to a frontend select it.
+    let named = match positionals.len() > 1 {
+        true => frontends.create(&positionals[0]),
+        false => None,
+    };
+    let mut positionals = positionals.into_iter();
+    let mut frontend = match (named, frontend_name) {
+        (Some(frontend), _) => {
+            positionals.next();
+            frontend
+        }
+        (None, Some(name)) => frontends.create(&name).ok_or_else(|| {
+            in_config(
+                config_file,
+                frontend_line,
+                format!("Unknown frontend \"{}\"", name),
+            )
+        })?,
+        (None, None) => frontend_options
+            .first()
+            .and_then(|(_, name, _)| {
+                frontends
+                    .all()
+                    .into_iter()
+                    .find(|f| f.options().iter().any(|o| o.name == *name))
+            })
+            .or_else(|| frontends.create(frontend::DEFAULT))
+            .ok_or("No frontend specified")?,
+    };
+    for (line, name, value) in frontend_options {
+        if !frontend.options().iter().any(|o| o.name == name) {
+            return Err(in_config(
+                config_file,
+                line,
+                format!(
+                    "--{} can't be used with the {} frontend",
+                    name,
+                    frontend.name()
+                ),
+            ));
+        }
+        frontend
+            .set_option(name, value)
+            .map_err(|e| in_config(config_file, line, e))?;
+    }
 
-    if load_address % 4 != 0 {
-        return Err("load_address must be word aligned".to_string());
+    let filename = positionals.next().ok_or("No filename specified")?;
+    if !interactive && frontend.prompts() {
+        return Err(format!(
+            "The {} frontend prompts for its arguments on stdin, which a test can't do",
+            frontend.name()
+        ));
+    }
+    frontend.parse(&mut positionals)?;
+
+    // The load address used to be given positionally, after everything else.
+    // It's still a command line option, so it overrides the config file.
+    if let Some(addr) = positionals.next() {
+        if load_address.is_some() && load_address_line.is_none() {
+            return Err("Load address specified twice".to_string());
+        }
+        load_address = Some(parse_addr(&addr, "Load address")?);
+    }
+    if let Some(extra) = positionals.next() {
+        return Err(format!("Unexpected argument \"{}\"", extra));
     }
 
     Ok(ParsedArgs {
         filename,
         frontend,
-        load_address,
+        load_address: load_address.unwrap_or(0),
         flags,
     })
 }
+
+fn parse_subcommand(
+    cmd: &Subcommand,
+    args: &[String],
+    frontends: &Registry,
+) -> Result<Command, String> {
+    let options = options(cmd, frontends);
+    let cl = split_command_line(&options, args)?;
+    if cl.help {
+        return Ok(Command::Help(subcommand_help(cmd, frontends)));
+    }
+
+    match cmd.name {
+        "run" | "debug" => {
+            let no_config = cl
+                .options
+                .iter()
+                .rev()
+                .find(|(name, _)| *name == "no-config");
+            let config = match matches!(no_config, Some((_, None))) {
+                true => Vec::new(),
+                false => read_config(&options)?,
+            };
+            let args = parse_run_args(
+                config::FILENAME,
+                config,
+                cl,
+                cmd.name == "debug",
+                true,
+                frontends,
+            )?;
+            Ok(Command::Run(Box::new(args)))
+        }
+        "asm" => {
+            let mut output = None;
+            for (name, value) in cl.options {
+                if name == "output" {
+                    output = value;
+                }
+            }
+            let mut positionals = cl.positionals.into_iter();
+            let input = positionals.next();
+            if let Some(extra) = positionals.next() {
+                return Err(format!("Unexpected argument \"{}\"", extra));
+            }
+            Ok(Command::Asm { input, output })
+        }
+        "disasm" => {
+            let mut load_address = 0;
+            let mut isa = None;
+            let mut strict_decoding = false;
+            for (name, value) in cl.options {
+                let value = value.unwrap_or_default();
+                match name {
+                    "load-address" => load_address = parse_addr(&value, "Load address")?,
+                    "isa" => isa = Some(isa::Profile::parse(&value)?),
+                    // `--strict-decoding=false` leaves it off
+                    "strict-decoding" => strict_decoding = value != "false",
+                    _ => {}
+                }
+            }
+            let mut positionals = cl.positionals.into_iter();
+            let filename = positionals.next().ok_or("No filename specified")?;
+            if let Some(extra) = positionals.next() {
+                return Err(format!("Unexpected argument \"{}\"", extra));
+            }
+            Ok(Command::Disasm {
+                filename,
+                load_address,
+                isa,
+                strict_decoding,
+            })
+        }
+        "test" => {
+            let mut jobs = None;
+            for (name, value) in cl.options {
+                if name == "jobs" {
+                    let value = value.unwrap_or_default();
+                    jobs = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|&n| n > 0)
+                            .ok_or_else(|| format!("Job count \"{}\" isn't valid", value))?,
+                    );
+                }
+            }
+            let mut positionals = cl.positionals.into_iter();
+            let spec = positionals.next().ok_or("No spec file specified")?;
+            if let Some(extra) = positionals.next() {
+                return Err(format!("Unexpected argument \"{}\"", extra));
+            }
+            Ok(Command::Test { spec, jobs })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Parse the command line, with `frontends` available to `run` and `debug`.
+pub fn parse_args(frontends: &Registry) -> Result<Command, ArgsError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let first = match args.first() {
+        Some(first) => first.as_str(),
+        None => {
+            return Err(ArgsError {
+                message: "No command or filename specified".to_string(),
+                command: None,
+            })
+        }
+    };
+
+    if first == "-h" || first == "--help" {
+        return Ok(Command::Help(top_level_help()));
+    }
+    if first == "help" {
+        return match args.get(1) {
+            None => Ok(Command::Help(top_level_help())),
+            Some(name) => match SUBCOMMANDS.iter().find(|cmd| cmd.name == name) {
+                Some(cmd) => Ok(Command::Help(subcommand_help(cmd, frontends))),
+                None => Err(ArgsError {
+                    message: format!("Unknown command \"{}\"", name),
+                    command: None,
+                }),
+            },
+        };
+    }
+
+    // Invocations without a subcommand are treated as `run`, which is how
+    // uwmips used to be used.
+    let (cmd, args) = match SUBCOMMANDS.iter().find(|cmd| cmd.name == first) {
+        Some(cmd) => (cmd, &args[1..]),
+        None => (&SUBCOMMANDS[0], &args[..]),
+    };
+    parse_subcommand(cmd, args, frontends).map_err(|message| ArgsError {
+        message,
+        command: Some(cmd.name),
+    })
+}
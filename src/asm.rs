@@ -0,0 +1,248 @@
+//! A small assembler for the CS241 dialect of MIPS assembly (as accepted by
+//! the course's `cs241.binasm`), plus the `j`/`jal`/`addi` instructions the
+//! VM also understands.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::instr::{I, J, R};
+
+/// An error in an assembly program
+#[derive(Debug)]
+pub struct Error {
+    /// 1-based line number the error occurred on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// A line which assembles to a word, after its labels have been stripped
+struct Line<'a> {
+    line: usize,
+    addr: u32,
+    text: &'a str,
+}
+
+/// Assemble a program into words, with labels resolved relative to address 0.
+pub fn assemble(src: &str) -> Result<Vec<u32>, Error> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+
+    // Pass 1: find where each label points
+    let mut addr: u32 = 0;
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let err = |message: String| Error { line, message };
+
+        let mut text = text.split(';').next().unwrap().trim();
+        while let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_label(label) {
+                return Err(err(format!("invalid label \"{}\"", label)));
+            }
+            if labels.insert(label, addr).is_some() {
+                return Err(err(format!("duplicate label \"{}\"", label)));
+            }
+            text = rest.trim();
+        }
+        if !text.is_empty() {
+            lines.push(Line { line, addr, text });
+            addr = addr
+                .checked_add(4)
+                .ok_or_else(|| err("program is too large".to_string()))?;
+        }
+    }
+
+    // Pass 2: encode each instruction
+    lines
+        .iter()
+        .map(|line| {
+            encode(line, &labels).map_err(|message| Error {
+                line: line.line,
+                message,
+            })
+        })
+        .collect()
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => chars.all(|c| c.is_ascii_alphanumeric()),
+        _ => false,
+    }
+}
+
+/// Parse a register (`$0` to `$31`)
+fn reg(s: &str) -> Result<u32, String> {
+    s.strip_prefix('$')
+        .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|n| n.parse().ok())
+        .filter(|&n| n < 32)
+        .ok_or_else(|| format!("expected a register, found \"{}\"", s))
+}
+
+/// Parse a decimal or `0x` prefixed hex integer, returning whether it was hex
+fn int(s: &str) -> Option<(i64, bool)> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let (radix, digits) = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(digits) if !negative => (16, digits),
+        Some(_) => return None,
+        None => (10, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let n = i64::from_str_radix(digits, radix).ok()?;
+    Some((if negative { -n } else { n }, radix == 16))
+}
+
+/// Parse a 16 bit immediate: decimal values are signed, hex values unsigned
+fn imm16(s: &str) -> Result<u32, String> {
+    let (n, hex) = int(s).ok_or_else(|| format!("expected an integer, found \"{}\"", s))?;
+    let in_range = if hex {
+        (0..=0xffff).contains(&n)
+    } else {
+        (-0x8000..=0x7fff).contains(&n)
+    };
+    if !in_range {
+        return Err(format!("immediate {} is out of range", s));
+    }
+    Ok(n as u32 & 0xffff)
+}
+
+/// Split operands on commas, checking there are exactly `n` of them
+fn operands(s: &str, n: usize) -> Result<Vec<&str>, String> {
+    let ops: Vec<&str> = if s.is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').map(str::trim).collect()
+    };
+    if ops.len() != n || ops.iter().any(|op| op.is_empty()) {
+        return Err(format!(
+            "expected {} operand{}",
+            n,
+            if n == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(ops)
+}
+
+fn encode(line: &Line, labels: &HashMap<&str, u32>) -> Result<u32, String> {
+    let (mnemonic, rest) = match line.text.split_once(char::is_whitespace) {
+        Some((mnemonic, rest)) => (mnemonic, rest.trim()),
+        None => (line.text, ""),
+    };
+    let label = |s: &str| {
+        labels
+            .get(s)
+            .copied()
+            .ok_or_else(|| format!("undefined label \"{}\"", s))
+    };
+
+    let r = |op: R, s: u32, t: u32, d: u32| s << 21 | t << 16 | d << 11 | op as u32;
+    let i = |op: I, s: u32, t: u32, i: u32| (op as u32) << 26 | s << 21 | t << 16 | i;
+
+    let word = match mnemonic {
+        ".word" => {
+            let ops = operands(rest, 1)?;
+            match int(ops[0]) {
+                Some((n, hex)) => {
+                    let in_range = if hex {
+                        (0..=0xffff_ffff).contains(&n)
+                    } else {
+                        (-0x8000_0000..=0xffff_ffff).contains(&n)
+                    };
+                    if !in_range {
+                        return Err(format!("{} doesn't fit in a word", ops[0]));
+                    }
+                    n as u32
+                }
+                None if is_label(ops[0]) => label(ops[0])?,
+                None => return Err(format!("expected a word, found \"{}\"", ops[0])),
+            }
+        }
+        "add" | "sub" | "slt" | "sltu" => {
+            let op = match mnemonic {
+                "add" => R::ADD,
+                "sub" => R::SUB,
+                "slt" => R::SLT,
+                _ => R::SLTU,
+            };
+            let ops = operands(rest, 3)?;
+            r(op, reg(ops[1])?, reg(ops[2])?, reg(ops[0])?)
+        }
+        "mult" | "multu" | "div" | "divu" => {
+            let op = match mnemonic {
+                "mult" => R::MULT,
+                "multu" => R::MULTU,
+                "div" => R::DIV,
+                _ => R::DIVU,
+            };
+            let ops = operands(rest, 2)?;
+            r(op, reg(ops[0])?, reg(ops[1])?, 0)
+        }
+        "mfhi" | "mflo" | "lis" => {
+            let op = match mnemonic {
+                "mfhi" => R::MFHI,
+                "mflo" => R::MFLO,
+                _ => R::LIS,
+            };
+            let ops = operands(rest, 1)?;
+            r(op, 0, 0, reg(ops[0])?)
+        }
+        "jr" | "jalr" => {
+            let op = if mnemonic == "jr" { R::JR } else { R::JALR };
+            let ops = operands(rest, 1)?;
+            r(op, reg(ops[0])?, 0, 0)
+        }
+        "beq" | "bne" => {
+            let op = if mnemonic == "beq" { I::BEQ } else { I::BNE };
+            let ops = operands(rest, 3)?;
+            let offset = if is_label(ops[2]) {
+                let offset = (label(ops[2])? as i64 - (line.addr as i64 + 4)) / 4;
+                if !(-0x8000..=0x7fff).contains(&offset) {
+                    return Err(format!("label \"{}\" is too far away", ops[2]));
+                }
+                offset as u32 & 0xffff
+            } else {
+                imm16(ops[2])?
+            };
+            i(op, reg(ops[0])?, reg(ops[1])?, offset)
+        }
+        "addi" => {
+            let ops = operands(rest, 3)?;
+            i(I::ADDI, reg(ops[1])?, reg(ops[0])?, imm16(ops[2])?)
+        }
+        "lw" | "sw" => {
+            let op = if mnemonic == "lw" { I::LW } else { I::SW };
+            let ops = operands(rest, 2)?;
+            let (offset, base) = ops[1]
+                .strip_suffix(')')
+                .and_then(|s| s.split_once('('))
+                .ok_or_else(|| format!("expected offset($reg), found \"{}\"", ops[1]))?;
+            i(op, reg(base.trim())?, reg(ops[0])?, imm16(offset.trim())?)
+        }
+        "j" | "jal" => {
+            let op = if mnemonic == "j" { J::J } else { J::JAL };
+            let ops = operands(rest, 1)?;
+            let target = match int(ops[0]) {
+                Some((n, _)) if (0..=0x3ff_ffff).contains(&n) => n as u32,
+                Some(_) => return Err(format!("jump target {} is out of range", ops[0])),
+                None if is_label(ops[0]) => label(ops[0])? >> 2,
+                None => return Err(format!("expected a jump target, found \"{}\"", ops[0])),
+            };
+            (op as u32) << 26 | target
+        }
+        _ => return Err(format!("unknown instruction \"{}\"", mnemonic)),
+    };
+    Ok(word)
+}
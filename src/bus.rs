@@ -1,47 +1,120 @@
-use std::io::Read;
-
+use crate::console::{Console, Input, Output};
+use crate::cpu::Error;
+use crate::device::Device;
 use crate::mem::MEM;
 
 /// Mediates CPU memory accesses.
+/// Accesses to addresses handled by a memory-mapped device are routed to that
+/// device, and everything else goes to main memory.
 #[derive(Clone, Debug)]
 pub struct Bus {
     mem: MEM,
+    console: Console,
+    devices: Vec<Box<dyn Device>>,
 }
 
 impl Bus {
-    /// Create a new Bus instance
+    /// Create a new Bus instance, with the console connected to stdin and
+    /// stdout
     pub fn new(mem: MEM) -> Bus {
-        Bus { mem }
+        Bus {
+            mem,
+            console: Console::new(Input::Stdin, Output::Stdout),
+            devices: Vec::new(),
+        }
+    }
+
+    /// Register an additional memory-mapped device.
+    /// Devices registered earlier take priority if their addresses overlap.
+    pub fn add_device(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+
+    /// The device that handles accesses to `addr`, if any.
+    pub fn device_at(&self, addr: u32) -> Option<&dyn Device> {
+        if self.console.maps(addr) {
+            return Some(&self.console);
+        }
+        self.devices
+            .iter()
+            .find(|d| d.maps(addr))
+            .map(|d| d.as_ref())
+    }
+
+    /// The device that handles accesses to `addr` (if any), along with main
+    /// memory.
+    fn route(&mut self, addr: u32) -> (Option<&mut dyn Device>, &mut MEM) {
+        let device: Option<&mut dyn Device> = if self.console.maps(addr) {
+            Some(&mut self.console)
+        } else {
+            match self.devices.iter_mut().find(|d| d.maps(addr)) {
+                Some(d) => Some(d.as_mut()),
+                None => None,
+            }
+        };
+        (device, &mut self.mem)
+    }
+
+    /// The memory-mapped console.
+    pub fn console(&self) -> &Console {
+        &self.console
+    }
+
+    /// The memory-mapped console.
+    pub fn console_mut(&mut self) -> &mut Console {
+        &mut self.console
+    }
+
+    /// Change where console reads from `0xffff0004` get their input from.
+    pub fn set_input(&mut self, input: Input) {
+        self.console.set_input(input)
+    }
+
+    /// Make console reads past the end of the input an error, instead of
+    /// returning -1.
+    pub fn set_strict_eof(&mut self, strict: bool) {
+        self.console.set_strict_eof(strict)
+    }
+
+    /// Enable the console's print and assert ports.
+    pub fn set_debug_ports(&mut self, enabled: bool) {
+        self.console.set_debug_ports(enabled)
+    }
+
+    /// Let devices know that the CPU executed an instruction.
+    pub fn tick(&mut self) {
+        self.console.tick();
+        for device in &mut self.devices {
+            device.tick();
+        }
     }
 
     /// Reads a value from a specified `addr` without side-effects.
+    /// Device registers with nothing to show read as 0. Use
+    /// [Bus::device_at] to tell those apart from real values.
     pub fn peek(&self, addr: u32) -> u32 {
-        match addr {
-            0xffff0004 => 0x10101010,
-            addr => self.mem.peek(addr),
+        match self.device_at(addr) {
+            Some(device) => device.peek(addr).unwrap_or(0),
+            None => self.mem.peek(addr),
         }
     }
 
     /// Read a value from a specified `addr`
-    /// Reads from `0xffff0004` get a char from stdin.
-    pub fn load(&mut self, addr: u32) -> u32 {
-        match addr {
-            0xffff0004 => std::io::stdin()
-                .bytes()
-                .next()
-                .expect("unexpectedly ran out of stdin")
-                .ok()
-                .unwrap() as u32,
-            addr => self.mem.load(addr),
+    pub fn load(&mut self, addr: u32) -> Result<u32, Error> {
+        match self.route(addr) {
+            (Some(device), mem) => device.load(addr, mem),
+            (None, mem) => Ok(mem.load(addr)),
         }
     }
 
     /// Write a value `val` into a specified `addr`
-    /// Writes to `0xffff0004` put a char onto stdout.
-    pub fn store(&mut self, addr: u32, val: u32) {
-        match addr {
-            0xffff000c => print!("{}", (val as u8) as char),
-            addr => self.mem.store(addr, val),
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), Error> {
+        match self.route(addr) {
+            (Some(device), mem) => device.store(addr, val, mem),
+            (None, mem) => {
+                mem.store(addr, val);
+                Ok(())
+            }
         }
     }
 }
@@ -0,0 +1,685 @@
+//! The `uwmips` command line interface.
+
+use std::any::Any;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Mutex};
+
+use crate::args::*;
+use crate::debug::Debugger;
+use crate::frontend::{Layout, Registry};
+use crate::spec::{self, Case};
+use crate::{
+    asm, bus, console, cpu, diff, dump, files, framebuffer, instr, isa, mem, merl, rng, runtime,
+    timer,
+};
+
+/// Write out any buffered program output, so that it shows up before any
+/// messages from the VM itself.
+fn flush_console(cpu: &mut cpu::CPU) {
+    if let Err(err) = cpu.bus_mut().console_mut().flush() {
+        eprintln!("Error! {:?}", err);
+    }
+}
+
+/// Run `uwmips` with the command line arguments the process was started with,
+/// and the given frontends available. Exits the process on error.
+pub fn main(frontends: Registry) {
+    match parse_args(&frontends) {
+        Ok(Command::Run(args)) => run(*args),
+        Ok(Command::Asm { input, output }) => assemble(input, output),
+        Ok(Command::Disasm {
+            filename,
+            load_address,
+            isa,
+            strict_decoding,
+        }) => disassemble(&filename, load_address, isa, strict_decoding),
+        Ok(Command::Test { spec, jobs }) => test(&spec, jobs, &frontends),
+        Ok(Command::Help(help)) => println!("{}", help),
+        Err(err) => {
+            eprintln!("Error! {}", err.message);
+            eprintln!("{}", err.hint());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Read a binary file into big-endian words
+fn read_program(filename: &str) -> Result<Vec<u32>, String> {
+    let data =
+        std::fs::read(filename).map_err(|e| format!("Failed to read {}: {}", filename, e))?;
+    if data.len() % 4 != 0 {
+        return Err(format!("{} is not word aligned", filename));
+    }
+    Ok(data
+        .chunks(4)
+        .map(|c| u32::from_be_bytes([c[0], c[1], c[2], c[3]]))
+        .collect())
+}
+
+/// Assemble a program, writing out the binary
+fn assemble(input: Option<String>, output: Option<String>) {
+    let src = match &input {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut src = String::new();
+            std::io::stdin().read_to_string(&mut src).map(|_| src)
+        }
+    };
+    let src = src.unwrap_or_else(|e| {
+        eprintln!(
+            "Failed to read {}: {}",
+            input.as_deref().unwrap_or("stdin"),
+            e
+        );
+        std::process::exit(1)
+    });
+
+    let words = asm::assemble(&src).unwrap_or_else(|e| {
+        eprintln!("Error! {}", e);
+        std::process::exit(1)
+    });
+    let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+
+    let res = match &output {
+        Some(path) => std::fs::write(path, &bytes),
+        None => std::io::stdout().write_all(&bytes),
+    };
+    if let Err(e) = res {
+        eprintln!(
+            "Failed to write {}: {}",
+            output.as_deref().unwrap_or("stdout"),
+            e
+        );
+        std::process::exit(1)
+    }
+}
+
+/// Words of `program` which hold code, as opposed to a MERL file's header or
+/// symbol table.
+fn code_range(program: &[u32]) -> Range<usize> {
+    match merl::parse(program) {
+        Some(Ok(_)) => 3..program[2] as usize / 4,
+        _ => 0..program.len(),
+    }
+}
+
+/// Print out each word of a binary as an instruction, marking any which
+/// aren't allowed by `isa`
+fn disassemble(
+    filename: &str,
+    load_address: u32,
+    isa: Option<isa::Profile>,
+    strict_decoding: bool,
+) {
+    let program = read_program(filename).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    let code = code_range(&program);
+    let disallowed: Vec<usize> = match &isa {
+        Some(isa) => isa::scan(&program[code.clone()], isa)
+            .into_iter()
+            .map(|(i, _)| code.start + i)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    for (i, &word) in program.iter().enumerate() {
+        let lenient = instr::Instr::from_u32(word);
+        let instr = match strict_decoding {
+            true => instr::Instr::from_u32_strict(word),
+            false => lenient,
+        };
+        let note = match (instr, lenient) {
+            _ if disallowed.contains(&i) => "   <- not allowed".to_string(),
+            // words which only strict decoding rejects
+            (instr::Instr::Inval(_, reason), instr::Instr::R { .. }) => format!("   <- {}", reason),
+            _ => String::new(),
+        };
+        println!(
+            "0x{:08x} | 0x{:08x} : {}{}",
+            load_address.wrapping_add(i as u32 * 4),
+            word,
+            instr,
+            note
+        );
+    }
+
+    if let Some(isa) = isa {
+        if !disallowed.is_empty() {
+            eprintln!(
+                "Error! {} instruction(s) aren't allowed by --isa {}",
+                disallowed.len(),
+                isa.name()
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Set up a register or part of memory, as asked for on the command line.
+fn apply_init(cpu: &mut cpu::CPU, init: Init) -> Result<(), String> {
+    match init {
+        Init::Reg { reg, val } => cpu.set_reg(reg, val).map_err(|e| format!("{:?}", e)),
+        Init::Mem { addr, val } => cpu.store(addr, val).map_err(|e| format!("{:?}", e)),
+        Init::MemFile { addr, path } => {
+            let words = read_program(&path)?;
+            if addr as u64 + words.len() as u64 * 4 > 1 << 32 {
+                return Err(format!("{} doesn't fit in memory", path));
+            }
+            for (i, word) in words.iter().enumerate() {
+                cpu.store(addr + i as u32 * 4, *word)
+                    .map_err(|e| format!("{:?}", e))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Why a program stopped running
+pub enum Exit {
+    /// The program jumped to the return address it started with in `$31`
+    Completed,
+    /// The CPU hit an error
+    Error(cpu::Error),
+    /// The program ran for `--max-steps` instructions without finishing
+    StepLimit,
+}
+
+/// Run the CPU until the program finishes, fails, or has executed `max_steps`
+/// instructions, passing any warnings to `warn` as they happen. Returns why it
+/// stopped, and the number of instructions run.
+fn execute(
+    cpu: &mut cpu::CPU,
+    max_steps: Option<u64>,
+    warn: &mut dyn FnMut(String),
+) -> (Exit, u64) {
+    let mut steps: u64 = 0;
+    let exit = loop {
+        // a program which returns on its last allowed step finished in time
+        if cpu.finished() {
+            break Exit::Completed;
+        }
+        if Some(steps) == max_steps {
+            break Exit::StepLimit;
+        }
+        let res = cpu.step();
+        cpu.take_warnings().into_iter().for_each(&mut *warn);
+        match res {
+            // only count instructions which actually ran, like the timer
+            Ok(true) => steps += 1,
+            Ok(false) => unreachable!(),
+            Err(err) => break Exit::Error(err),
+        }
+    };
+    (exit, steps)
+}
+
+/// Construct the VM described by `args`, with the program loaded and the
+/// frontend's arguments set up, ready to run.
+fn load(args: &mut ParsedArgs) -> Result<cpu::CPU, String> {
+    let ParsedArgs {
+        filename,
+        frontend,
+        load_address,
+        flags,
+    } = args;
+    let load_address = *load_address;
+
+    // reported through the CPU, once there is one
+    let mut warnings = Vec::new();
+    let mem = mem::MEM::new();
+    let mut bus = bus::Bus::new(mem);
+    bus.set_strict_eof(flags.strict_eof);
+    bus.set_debug_ports(flags.debug_ports);
+    match &flags.input {
+        None => {}
+        Some(ProgramInput::String(s)) => bus.set_input(console::Input::from_bytes(s.clone())),
+        Some(ProgramInput::File(path)) => {
+            let data = std::fs::read(path)
+                .map_err(|e| format!("Failed to read input file {}: {}", path, e))?;
+            bus.set_input(console::Input::from_bytes(data));
+        }
+    }
+    if let Some(path) = &flags.output {
+        let f = File::create(path)
+            .map_err(|e| format!("Failed to create output file {}: {}", path, e))?;
+        bus.console_mut().set_output(console::Output::from_file(f));
+    }
+    if let Some((width, height)) = flags.framebuffer {
+        let dir = flags.frame_dir.clone().unwrap_or_else(|| ".".to_string());
+        bus.add_device(Box::new(framebuffer::Framebuffer::new(width, height, dir)));
+    }
+    if flags.timer {
+        bus.add_device(Box::new(timer::Timer::new()));
+    }
+    if flags.rng {
+        let seed = flags.seed.unwrap_or_else(|| {
+            let seed = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64);
+            warnings.push(format!(
+                "Using random seed {} (rerun with --seed {} to reproduce)",
+                seed, seed
+            ));
+            seed
+        });
+        bus.add_device(Box::new(rng::Rng::new(seed)));
+    }
+    if let Some(dir) = &flags.sandbox_dir {
+        let files = files::Files::new(dir)
+            .map_err(|e| format!("Failed to open sandbox directory {}: {}", dir, e))?;
+        bus.add_device(Box::new(files));
+    }
+    let mut cpu = cpu::CPU::new(bus, load_address);
+    warnings.into_iter().for_each(|w| cpu.warn(w));
+    cpu.set_strict_decoding(flags.strict_decoding);
+    cpu.set_overflow(flags.overflow);
+    if let Some(isa) = &flags.isa {
+        cpu.set_isa(isa.clone());
+    }
+
+    // Step 1: Load program into memory
+    let mut program = read_program(filename)?;
+    // Point out disallowed instructions up front, even ones which never run.
+    // They might just be data, so only running one is an error.
+    if let Some(isa) = &flags.isa {
+        let code = code_range(&program);
+        for (i, instr) in isa::scan(&program[code.clone()], isa) {
+            let addr = load_address.wrapping_add((code.start + i) as u32 * 4);
+            cpu.warn(format!(
+                "Warning! {} isn't allowed by --isa {} (at 0x{:08x})",
+                instr,
+                isa.name(),
+                addr
+            ));
+        }
+    }
+    let program_end = load_address as u64 + program.len() as u64 * 4;
+    if program_end > 1 << 32 {
+        return Err(format!(
+            "The program doesn't fit in memory at 0x{:08x}",
+            load_address
+        ));
+    }
+
+    if flags.runtime {
+        // Link unlinked MERL files against the native runtime procedures
+        if let Some(merl) = merl::parse(&program) {
+            let merl = merl?;
+            for location in merl.relocations {
+                let word = &mut program[location as usize / 4];
+                *word = word.wrapping_add(load_address);
+            }
+            for (location, name) in merl.imports {
+                let procedure = runtime::Procedure::from_name(&name)
+                    .ok_or_else(|| format!("Unresolved import: {}", name))?;
+                program[location as usize / 4] = procedure.addr();
+            }
+        }
+    }
+
+    for (i, word) in program.iter().enumerate() {
+        let _ = cpu.store(load_address + (i as u32) * 4, *word);
+    }
+
+    // Apply any --reg, --mem and --mem-file initialization
+    for (option, init) in std::mem::take(&mut flags.inits) {
+        apply_init(&mut cpu, init).map_err(|e| format!("{}: {}", option, e))?;
+    }
+
+    // Step 2: Load args into memory
+    let mut layout = Layout::new(
+        filename.clone(),
+        load_address as u64..program_end,
+        flags.array_address.unwrap_or(program_end as u32),
+    );
+    frontend.init(&mut cpu, &mut layout)?;
+    if flags.runtime {
+        // the heap goes after the program and anything the frontend put after it
+        let heap_start = match layout.data() {
+            Some((_, data)) => data.end.max(program_end),
+            None => program_end,
+        };
+        let heap_start = heap_start.min(u32::MAX as u64) as u32;
+        cpu.set_runtime(runtime::Runtime::new(heap_start));
+    }
+    if flags.array_address.is_some() && layout.data().is_none() {
+        return Err(format!(
+            "--array-address was given, but the {} frontend doesn't put anything in memory",
+            frontend.name()
+        ));
+    }
+
+    if flags.verbose {
+        eprintln!(
+            "Program: 0x{:08x}..0x{:08x} ({} words)",
+            load_address,
+            program_end,
+            program.len()
+        );
+        if let Some((what, data)) = layout.data() {
+            eprintln!(
+                "Data:    0x{:08x}..0x{:08x} ({} words, {})",
+                data.start,
+                data.end,
+                (data.end - data.start) / 4,
+                what
+            );
+        }
+        eprintln!(
+            "Stack:   grows down from 0x{:08x}",
+            cpu.get_reg(cpu::Reg::Reg(30)).unwrap()
+        );
+    }
+
+    Ok(cpu)
+}
+
+fn run(mut args: ParsedArgs) {
+    let expected_output = args.flags.expect_output.as_ref().map(|path| {
+        std::fs::read(path).unwrap_or_else(|e| {
+            eprintln!("Failed to read expected output file {}: {}", path, e);
+            std::process::exit(1)
+        })
+    });
+
+    let mut cpu = load(&mut args).unwrap_or_else(|e| {
+        eprintln!("Error! {}", e);
+        std::process::exit(1)
+    });
+    for warning in cpu.take_warnings() {
+        eprintln!("{}", warning);
+    }
+    if expected_output.is_some() || args.flags.dump_json.is_some() {
+        cpu.bus_mut().console_mut().capture_output();
+    }
+    let flags = args.flags;
+
+    // Step 3: Run the VM
+    if flags.debug {
+        let mut debugger = Debugger::new(cpu);
+        if let Some(rows) = flags.debug_context {
+            debugger.set_context(rows);
+        }
+        if let Some(steps) = flags.debug_history {
+            debugger.set_history(steps);
+        }
+        if let Some(steps) = flags.max_steps {
+            debugger.set_max_steps(steps);
+        }
+        if let Err(msg) = debugger.debug() {
+            eprintln!("Error! {}", msg);
+            std::process::exit(1);
+        }
+        cpu = debugger.into_cpu();
+    } else {
+        if flags.compat {
+            eprintln!("Running MIPS program.");
+        }
+        let (exit, steps) = execute(&mut cpu, flags.max_steps, &mut |w| eprintln!("{}", w));
+        flush_console(&mut cpu);
+        let pc = cpu.get_reg(cpu::Reg::PC).unwrap();
+        match &exit {
+            Exit::Completed if flags.compat => eprintln!("MIPS program completed normally."),
+            Exit::Completed => eprintln!("Execution completed successfully!"),
+            Exit::Error(err) => eprintln!("Error! {:?} (pc = 0x{:08x})", err, pc),
+            Exit::StepLimit => eprintln!(
+                "Error! Reached the step limit of {} instructions (pc = 0x{:08x})",
+                steps, pc
+            ),
+        }
+        // Dump final CPU state
+        match flags.compat {
+            true => eprintln!("{}", cpu.reference_dump()),
+            false => eprintln!("{}", cpu),
+        }
+
+        if let Some(path) = &flags.dump_json {
+            let json = dump::to_json(&cpu, &exit, steps, &flags.dump_mem);
+            if let Err(e) = std::fs::write(path, json) {
+                eprintln!("Failed to write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Step 4: Check for memory leaks
+    if let Some(runtime) = cpu.runtime() {
+        let leaks = runtime.leaks();
+        if !leaks.is_empty() {
+            let words: u32 = leaks.iter().map(|b| b.len).sum();
+            eprintln!(
+                "Leak report: {} allocation(s) ({} words) never deleted",
+                leaks.len(),
+                words
+            );
+            for block in leaks {
+                eprintln!("  0x{:08x} ({} words)", block.addr, block.len);
+            }
+        }
+    }
+
+    // Step 5: Check the program's output
+    if let Some(expected) = expected_output {
+        let actual = cpu.bus().console().captured().unwrap_or_default();
+        match diff::diff_bytes(&expected, actual) {
+            None => eprintln!("Output matches expected output."),
+            Some(report) => {
+                eprintln!("{}", report);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// The result of running a test case
+struct Outcome {
+    /// Number of instructions the program ran for, if it got to run at all
+    steps: Option<u64>,
+    /// Everything that didn't go as expected
+    failures: Vec<String>,
+    /// Warnings the program caused, which don't fail the case by themselves
+    warnings: Vec<String>,
+}
+
+/// Run a test case, and check it against its expectations.
+fn run_case(case: Case) -> Outcome {
+    let Case {
+        mut args, expect, ..
+    } = case;
+    let fail = |failure: String| Outcome {
+        steps: None,
+        failures: vec![failure],
+        warnings: Vec::new(),
+    };
+
+    let expected_output = match &args.flags.expect_output {
+        None => expect.output,
+        Some(path) => match std::fs::read(path) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                return fail(format!(
+                    "Failed to read expected output file {}: {}",
+                    path, e
+                ))
+            }
+        },
+    };
+    // Programs don't get to read the runner's stdin
+    if args.flags.input.is_none() {
+        args.flags.input = Some(ProgramInput::String(String::new()));
+    }
+
+    let mut cpu = match load(&mut args) {
+        Ok(cpu) => cpu,
+        Err(e) => return fail(e),
+    };
+    let console = cpu.bus_mut().console_mut();
+    console.set_output(console::Output::Discard);
+    console.capture_output();
+
+    let mut warnings = cpu.take_warnings();
+    let (exit, steps) = execute(&mut cpu, args.flags.max_steps, &mut |w| warnings.push(w));
+    let pc = cpu.get_reg(cpu::Reg::PC).unwrap();
+    let mut failures = Vec::new();
+    match exit {
+        Exit::Completed => {}
+        Exit::Error(err) => failures.push(format!("Error! {:?} (pc = 0x{:08x})", err, pc)),
+        Exit::StepLimit => failures.push(format!(
+            "Reached the step limit of {} instructions (pc = 0x{:08x})",
+            steps, pc
+        )),
+    }
+
+    for (name, reg, expected) in expect.regs {
+        match cpu.get_reg(reg) {
+            Ok(actual) if actual == expected => {}
+            Ok(actual) => failures.push(format!(
+                "expected {}, got 0x{:08x} ({})",
+                name, actual, actual as i32
+            )),
+            Err(err) => failures.push(format!("expected {}: {:?}", name, err)),
+        }
+    }
+    for (addr, expected) in expect.mem {
+        let actual = cpu.peek(addr);
+        if actual != expected {
+            failures.push(format!(
+                "0x{:08x}: expected 0x{:08x} ({}), got 0x{:08x} ({})",
+                addr, expected, expected as i32, actual, actual as i32
+            ));
+        }
+    }
+    if let Some(expected) = expected_output {
+        let actual = cpu.bus().console().captured().unwrap_or_default();
+        if let Some(report) = diff::diff_bytes(&expected, actual) {
+            failures.push(report);
+        }
+    }
+
+    Outcome {
+        steps: Some(steps),
+        failures,
+        warnings,
+    }
+}
+
+/// The message a panic was started with, if it has one
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "(no message)"
+    }
+}
+
+/// Run every case in a spec file on a pool of `jobs` worker threads, and
+/// report which ones failed. Results are always reported in the order the
+/// cases appear in the spec, no matter which order they finish in.
+fn test(spec: &str, jobs: Option<usize>, frontends: &Registry) {
+    let cases = spec::read(spec, frontends).unwrap_or_else(|e| {
+        eprintln!("Error! {}", e);
+        std::process::exit(1)
+    });
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .min(cases.len());
+
+    let names: Vec<String> = cases.iter().map(|c| c.name.clone()).collect();
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!("{:<width$}  RESULT  STEPS", "CASE", width = width);
+
+    let queue = Mutex::new(cases.into_iter().enumerate());
+    let (tx, rx) = mpsc::channel();
+    let mut results: Vec<Option<Outcome>> = names.iter().map(|_| None).collect();
+    std::thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let queue = &queue;
+            s.spawn(move || loop {
+                // the lock is only held while taking the next case
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some((i, case)) => {
+                        // a bug in the VM shouldn't take the rest of the cases
+                        // down with it
+                        let outcome = panic::catch_unwind(AssertUnwindSafe(|| run_case(case)))
+                            .unwrap_or_else(|payload| Outcome {
+                                steps: None,
+                                failures: vec![format!(
+                                    "uwmips panicked: {}",
+                                    panic_message(&payload)
+                                )],
+                                warnings: Vec::new(),
+                            });
+                        let _ = tx.send((i, outcome));
+                    }
+                    None => break,
+                }
+            });
+        }
+        drop(tx);
+
+        // Print each result once every case before it has finished
+        let mut printed = 0;
+        for (i, outcome) in rx {
+            results[i] = Some(outcome);
+            while let Some(Some(outcome)) = results.get(printed) {
+                println!(
+                    "{:<width$}  {:<6}  {}",
+                    names[printed],
+                    if outcome.failures.is_empty() {
+                        "pass"
+                    } else {
+                        "FAIL"
+                    },
+                    outcome
+                        .steps
+                        .map_or("-".to_string(), |steps| steps.to_string()),
+                    width = width
+                );
+                printed += 1;
+            }
+        }
+    });
+    let results: Vec<(String, Outcome)> = names
+        .into_iter()
+        .zip(results.into_iter().map(|outcome| outcome.unwrap()))
+        .collect();
+
+    for (name, outcome) in &results {
+        if outcome.failures.is_empty() && outcome.warnings.is_empty() {
+            continue;
+        }
+        println!("\n{}:", name);
+        for message in outcome.failures.iter().chain(&outcome.warnings) {
+            println!("  {}", message.replace('\n', "\n  "));
+        }
+    }
+    let failed: Vec<_> = results
+        .iter()
+        .filter(|(_, outcome)| !outcome.failures.is_empty())
+        .collect();
+
+    println!(
+        "\n{} passed, {} failed",
+        results.len() - failed.len(),
+        failed.len()
+    );
+    if !failed.is_empty() {
+        std::process::exit(1);
+    }
+}
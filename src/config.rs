@@ -0,0 +1,228 @@
+//! Project configuration files (`uwmips.toml`), which set defaults for
+//! `uwmips run` and `uwmips debug`.
+//!
+//! Only a small subset of TOML is supported: `[section]` headers, and
+//! `key = value` pairs where the value is a boolean, an integer (decimal or
+//! `0x` hex), a double-quoted string (with `\n`, `\t`, `\"`, `\\` and `\uXXXX`
+//! escapes), or a single-line array of those.
+
+use std::fmt;
+
+/// Name of the configuration file, looked for in the working directory.
+pub const FILENAME: &str = "uwmips.toml";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Array(Vec<Value>),
+}
+
+/// A `key = value` pair.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// 1-based line number the entry is on
+    pub line: usize,
+    /// The `[section]` the entry is in, if any
+    pub section: Option<String>,
+    pub key: String,
+    pub value: Value,
+}
+
+/// An error in a configuration file
+#[derive(Debug)]
+pub struct Error {
+    /// 1-based line number the error occurred on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parse a configuration file into its entries, in the order they appear.
+pub fn parse(src: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut section = None;
+
+    for (i, text) in src.lines().enumerate() {
+        let line = i + 1;
+        let err = |message: String| Error { line, message };
+
+        let text = strip_comment(text).trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = text.strip_prefix('[') {
+            let name = name
+                .strip_suffix(']')
+                .map(str::trim)
+                .filter(|name| is_key(name))
+                .ok_or_else(|| err(format!("invalid section header \"{}\"", text)))?;
+            section = Some(name.to_string());
+            continue;
+        }
+
+        let (key, value) = text
+            .split_once('=')
+            .ok_or_else(|| err(format!("expected key = value, found \"{}\"", text)))?;
+        let key = key.trim();
+        if !is_key(key) {
+            return Err(err(format!("invalid key \"{}\"", key)));
+        }
+        if entries.iter().any(|e| e.section == section && e.key == key) {
+            return Err(err(format!("duplicate key \"{}\"", key)));
+        }
+
+        let value = parse_value(value.trim()).map_err(err)?;
+        entries.push(Entry {
+            line,
+            section: section.clone(),
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn is_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Remove a `#` comment, as long as it isn't inside a string
+fn strip_comment(s: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &s[..i],
+            _ => {}
+        }
+    }
+    s
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if let Some(items) = s.strip_prefix('[') {
+        let items = items
+            .strip_suffix(']')
+            .ok_or_else(|| format!("unterminated array \"{}\"", s))?;
+        return split_items(items)?
+            .into_iter()
+            .map(|item| match parse_value(item)? {
+                Value::Array(_) => Err("nested arrays aren't supported".to_string()),
+                value => Ok(value),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Array);
+    }
+
+    if let Some(string) = s.strip_prefix('"') {
+        let string = string
+            .strip_suffix('"')
+            .ok_or_else(|| format!("unterminated string {}", s))?;
+        return unescape(string).map(Value::String);
+    }
+
+    match s {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+
+    let digits = s.replace('_', "");
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits.strip_prefix('+').unwrap_or(&digits)),
+    };
+    let n = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| format!("invalid value \"{}\"", s))?;
+    Ok(Value::Int(if negative { -n } else { n }))
+}
+
+/// Split the contents of an array on commas which aren't inside strings
+fn split_items(s: &str) -> Result<Vec<&str>, String> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                items.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = s[start..].trim();
+    // allow a trailing comma
+    if !last.is_empty() {
+        items.push(last);
+    }
+    if items.iter().any(|item| item.is_empty()) {
+        return Err(format!("empty array element in [{}]", s));
+    }
+    Ok(items)
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let c = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid escape \\u{}", hex))?;
+                out.push(c);
+            }
+            Some(c) => return Err(format!("unknown escape \\{}", c)),
+            None => return Err("string ends with a \\".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+impl fmt::Display for Value {
+    /// Formats the value the way it would be given on the command line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Int(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+                write!(f, "{}", items.join(" "))
+            }
+        }
+    }
+}
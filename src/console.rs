@@ -0,0 +1,255 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::Error;
+use crate::device::Device;
+use crate::mem::MEM;
+
+/// Reads from this address get a char from the console input.
+pub const INPUT_ADDR: u32 = 0xffff0004;
+/// Writes to this address put a char onto the console output.
+pub const OUTPUT_ADDR: u32 = 0xffff000c;
+/// With debug ports enabled, writes to this address print the value in signed
+/// decimal, followed by a newline.
+pub const PRINT_DEC_ADDR: u32 = 0xffff0010;
+/// With debug ports enabled, writes to this address print the value in hex,
+/// followed by a newline.
+pub const PRINT_HEX_ADDR: u32 = 0xffff0014;
+/// With debug ports enabled, writing 0 to this address stops the program with
+/// [Error::AssertFailed].
+pub const ASSERT_ADDR: u32 = 0xffff0018;
+
+/// Where the console reads its input from.
+#[derive(Clone, Debug)]
+pub enum Input {
+    /// The host process's stdin
+    Stdin,
+    /// An in-memory buffer, consumed front to back
+    Buffer { data: Arc<[u8]>, pos: usize },
+}
+
+impl Input {
+    /// Create a new Input which reads from the given bytes.
+    pub fn from_bytes(data: impl Into<Vec<u8>>) -> Input {
+        Input::Buffer {
+            data: data.into().into(),
+            pos: 0,
+        }
+    }
+
+    /// Look at the next byte of input without consuming it.
+    /// Returns `None` if that can't be done without blocking (i.e: stdin), and
+    /// `Some(None)` at EOF.
+    fn peek_byte(&self) -> Option<Option<u8>> {
+        match self {
+            Input::Stdin => None,
+            Input::Buffer { data, pos } => Some(data.get(*pos).copied()),
+        }
+    }
+
+    /// Read the next byte of input, or `None` at EOF.
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        match self {
+            Input::Stdin => std::io::stdin().lock().bytes().next().transpose(),
+            Input::Buffer { data, pos } => {
+                let b = data.get(*pos).copied();
+                if b.is_some() {
+                    *pos += 1;
+                }
+                Ok(b)
+            }
+        }
+    }
+}
+
+/// Where the console writes its output to.
+#[derive(Clone, Debug)]
+pub enum Output {
+    /// The host process's stdout
+    Stdout,
+    /// A file on disk
+    File(Arc<Mutex<File>>),
+    /// Nowhere (output is only kept if captured)
+    Discard,
+}
+
+impl Output {
+    /// Create a new Output which writes to the given file.
+    pub fn from_file(file: File) -> Output {
+        Output::File(Arc::new(Mutex::new(file)))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Output::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(buf)?;
+                stdout.flush()
+            }
+            Output::File(file) => file.lock().unwrap().write_all(buf),
+            Output::Discard => Ok(()),
+        }
+    }
+}
+
+/// Memory-mapped console.
+/// Reads from `0xffff0004` get a char from the input, and writes to
+/// `0xffff000c` put a char onto the output.
+///
+/// Output is buffered, and only written out on newlines, before reading
+/// input, or when explicitly flushed with [Console::flush].
+#[derive(Clone, Debug)]
+pub struct Console {
+    input: Input,
+    output: Output,
+    /// Output which hasn't been flushed yet
+    pending: Vec<u8>,
+    /// The last char written to the output
+    last_output: Option<u8>,
+    /// Copy of everything written to the output, if capturing is enabled
+    captured: Option<Vec<u8>>,
+    /// Treat reading past the end of the input as an error, instead of
+    /// returning -1 like the course VM does.
+    strict_eof: bool,
+    /// Handle the print and assert ports. These aren't part of the course VM,
+    /// so they're off by default.
+    debug_ports: bool,
+}
+
+impl Console {
+    /// Create a new Console reading from `input` and writing to `output`
+    pub fn new(input: Input, output: Output) -> Console {
+        Console {
+            input,
+            output,
+            pending: Vec::new(),
+            last_output: None,
+            captured: None,
+            strict_eof: false,
+            debug_ports: false,
+        }
+    }
+
+    /// Change where the console reads its input from.
+    pub fn set_input(&mut self, input: Input) {
+        self.input = input;
+    }
+
+    /// Change where the console writes its output to.
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    /// Start keeping a copy of all output in memory.
+    /// See [Console::captured].
+    pub fn capture_output(&mut self) {
+        self.captured.get_or_insert_with(Vec::new);
+    }
+
+    /// Everything written to the console since capturing was enabled, or
+    /// `None` if output isn't being captured.
+    pub fn captured(&self) -> Option<&[u8]> {
+        self.captured.as_deref()
+    }
+
+    /// Make reading past the end of the input an error.
+    pub fn set_strict_eof(&mut self, strict: bool) {
+        self.strict_eof = strict;
+    }
+
+    /// Enable the print and assert ports.
+    pub fn set_debug_ports(&mut self, enabled: bool) {
+        self.debug_ports = enabled;
+    }
+
+    /// Read a single char of input.
+    /// Returns 0xffffffff (-1) at EOF, unless `strict_eof` is set.
+    pub fn read(&mut self) -> Result<u32, Error> {
+        // make sure any prompts are visible before blocking on input
+        self.flush()?;
+        match self.input.next_byte().map_err(Error::ConsoleRead)? {
+            Some(b) => Ok(b as u32),
+            None if self.strict_eof => Err(Error::UnexpectedEof),
+            None => Ok(0xffffffff),
+        }
+    }
+
+    /// Write a single char of output.
+    pub fn write(&mut self, val: u32) -> Result<(), Error> {
+        let b = val as u8;
+        if let Some(captured) = &mut self.captured {
+            captured.push(b);
+        }
+        self.pending.push(b);
+        self.last_output = Some(b);
+        if b == b'\n' {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write a string to the output.
+    pub fn write_str(&mut self, s: &str) -> Result<(), Error> {
+        for b in s.bytes() {
+            self.write(b as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Write out any pending output.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let res = self.output.write_all(&self.pending);
+        self.pending.clear();
+        res.map_err(Error::ConsoleWrite)
+    }
+}
+
+impl Device for Console {
+    fn name(&self) -> &'static str {
+        "console"
+    }
+
+    fn maps(&self, addr: u32) -> bool {
+        match addr {
+            INPUT_ADDR | OUTPUT_ADDR => true,
+            PRINT_DEC_ADDR | PRINT_HEX_ADDR | ASSERT_ADDR => self.debug_ports,
+            _ => false,
+        }
+    }
+
+    fn peek(&self, addr: u32) -> Option<u32> {
+        match addr {
+            INPUT_ADDR => match self.input.peek_byte()? {
+                Some(b) => Some(b as u32),
+                None if self.strict_eof => None,
+                None => Some(0xffffffff),
+            },
+            OUTPUT_ADDR => self.last_output.map(|b| b as u32),
+            // the debug ports are write-only
+            _ => None,
+        }
+    }
+
+    fn load(&mut self, addr: u32, _mem: &mut MEM) -> Result<u32, Error> {
+        match addr {
+            INPUT_ADDR => self.read(),
+            // the output registers can't be read back
+            _ => Ok(0),
+        }
+    }
+
+    fn store(&mut self, addr: u32, val: u32, _mem: &mut MEM) -> Result<(), Error> {
+        match addr {
+            OUTPUT_ADDR => self.write(val),
+            PRINT_DEC_ADDR => self.write_str(&format!("{}\n", val as i32)),
+            PRINT_HEX_ADDR => self.write_str(&format!("0x{:08x}\n", val)),
+            ASSERT_ADDR if val == 0 => Err(Error::AssertFailed),
+            // the input register can't be written to
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use crate::bus::Bus;
 use crate::instr::Instr;
+use crate::isa::Profile;
+use crate::runtime::{Procedure, Runtime};
 
 #[derive(Clone)]
 pub struct CPU {
@@ -13,15 +15,86 @@ pub struct CPU {
     hi: u32,
     /// lo multiplication / division register
     lo: u32,
+    /// Native runtime procedures (`print`, `new`, etc...), if enabled
+    runtime: Option<Runtime>,
+    /// Instructions the program is allowed to use, if it's restricted
+    isa: Option<Profile>,
+    /// Reject instructions with junk in their unused fields
+    strict_decoding: bool,
+    /// What to do when a signed arithmetic instruction overflows
+    overflow: Overflow,
+    /// Warnings which haven't been taken by [CPU::take_warnings] yet
+    warnings: Vec<String>,
+}
+
+/// What to do when `add`, `sub`, `addi` or `mult` overflows (for `mult`, when
+/// the product doesn't fit in `lo`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Silently wrap around, like the course VM
+    Wrap,
+    /// Wrap around, but record a warning (see [CPU::take_warnings])
+    Warn,
+    /// Stop with an [Error::Overflow], like a real MIPS `add` trap
+    Error,
 }
 
 #[derive(Debug)]
 pub enum Error {
     InvalidReg,
-    BadInstr,
+    /// The word at the PC isn't a valid instruction, for the given reason
+    BadInstr(&'static str),
+    /// Failed to read from the console input
+    ConsoleRead(std::io::Error),
+    /// Read past the end of the console input (only with strict EOF handling)
+    UnexpectedEof,
+    /// Failed to write to the console output
+    ConsoleWrite(std::io::Error),
+    /// Failed to dump a frame from the framebuffer
+    FramebufferDump(std::io::Error),
+    /// Zero was stored to the debug assert port
+    AssertFailed,
+    /// `new` or `delete` was called before `init`
+    HeapNotInitialized,
+    /// `delete` was called on an already deleted pointer
+    DoubleFree(u32),
+    /// `delete` was called on a pointer which didn't come from `new`
+    InvalidFree(u32),
+    /// The instruction isn't allowed by the ISA profile
+    DisallowedInstr(Instr),
+    /// A signed arithmetic instruction overflowed, with the given operands
+    Overflow {
+        instr: Instr,
+        a: i32,
+        b: i32,
+    },
+    /// `div` or `divu` was run with a divisor of zero
+    DivideByZero,
+}
+
+impl Error {
+    /// Name of the kind of error, without any details
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::InvalidReg => "InvalidReg",
+            Error::BadInstr(_) => "BadInstr",
+            Error::ConsoleRead(_) => "ConsoleRead",
+            Error::UnexpectedEof => "UnexpectedEof",
+            Error::ConsoleWrite(_) => "ConsoleWrite",
+            Error::FramebufferDump(_) => "FramebufferDump",
+            Error::AssertFailed => "AssertFailed",
+            Error::HeapNotInitialized => "HeapNotInitialized",
+            Error::DoubleFree(_) => "DoubleFree",
+            Error::InvalidFree(_) => "InvalidFree",
+            Error::DisallowedInstr(_) => "DisallowedInstr",
+            Error::Overflow { .. } => "Overflow",
+            Error::DivideByZero => "DivideByZero",
+        }
+    }
 }
 
 /// CPU Register. Used for traces / debugging.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Reg {
     /// Program Counter
     PC,
@@ -42,9 +115,14 @@ impl CPU {
             reg: [0; 32],
             hi: 0,
             lo: 0,
+            runtime: None,
+            isa: None,
+            strict_decoding: false,
+            overflow: Overflow::Wrap,
+            warnings: Vec::new(),
         };
         cpu.reg[29] = load_addr;
-        cpu.reg[30] = 0x01000000 + load_addr;
+        cpu.reg[30] = 0x01000000u32.wrapping_add(load_addr);
         cpu.reg[31] = 0x8123456c;
         cpu
     }
@@ -55,15 +133,62 @@ impl CPU {
     }
 
     /// Perform a load in the CPU memory space.
-    pub fn load(&mut self, addr: u32) -> u32 {
+    pub fn load(&mut self, addr: u32) -> Result<u32, Error> {
         self.mem.load(addr)
     }
 
     /// Perform a store in the CPU memory space.
-    pub fn store(&mut self, addr: u32, val: u32) {
+    pub fn store(&mut self, addr: u32, val: u32) -> Result<(), Error> {
         self.mem.store(addr, val)
     }
 
+    /// The CPU's address space.
+    pub fn bus(&self) -> &Bus {
+        &self.mem
+    }
+
+    /// The CPU's address space.
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.mem
+    }
+
+    /// Enable native implementations of the runtime procedures, called by
+    /// jumping to their reserved addresses (see [crate::runtime]).
+    pub fn set_runtime(&mut self, runtime: Runtime) {
+        self.runtime = Some(runtime);
+    }
+
+    /// State of the native runtime procedures, if enabled.
+    pub fn runtime(&self) -> Option<&Runtime> {
+        self.runtime.as_ref()
+    }
+
+    /// Treat instructions whose unused fields aren't zero as invalid, like the
+    /// course VM does. See [Instr::from_u32_strict].
+    pub fn set_strict_decoding(&mut self, strict: bool) {
+        self.strict_decoding = strict;
+    }
+
+    /// Set what happens when a signed arithmetic instruction overflows.
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Record a warning about the program, for whoever is running it to report.
+    pub fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    /// Take the warnings recorded so far, oldest first.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Only allow the program to run instructions in `profile`.
+    pub fn set_isa(&mut self, profile: Profile) {
+        self.isa = Some(profile);
+    }
+
     /// Set a register's value.
     /// Returns a Error::InvalidReg if the register index is out of bounds.
     pub fn set_reg(&mut self, reg: Reg, val: u32) -> Result<(), Error> {
@@ -100,23 +225,106 @@ impl CPU {
         Ok(val)
     }
 
+    /// Check if the program has returned to the "OS" (i.e: jumped to the
+    /// initial value of `$31`).
+    pub fn finished(&self) -> bool {
+        self.pc == 0x8123456c
+    }
+
     /// Tick the CPU forward a single iteration
     /// Returns a bool indicating if the CPU is still running, or an [Error] if
     /// something went wrong.
+    /// If the instruction fails, the PC is left pointing at it (or for a runtime
+    /// procedure, at the call to it).
     pub fn step(&mut self) -> Result<bool, Error> {
-        // Check for jump back to "OS"
-        if self.pc == 0x8123456c {
+        if self.finished() {
             return Ok(false);
         }
 
-        let instr = Instr::from_u32(self.mem.load(self.pc));
+        let pc = self.pc;
+        if let Err(err) = self.exec() {
+            self.pc = match self.runtime.is_some() && Procedure::at(pc).is_some() {
+                // point at the call, rather than the procedure's address
+                true => self.reg[31].wrapping_sub(4),
+                false => pc,
+            };
+            return Err(err);
+        }
+
+        // Enforce that reg[0] is always 0
+        self.reg[0] = 0;
+
+        self.mem.tick();
+
+        Ok(true)
+    }
+
+    /// Run a native runtime procedure, and return to the caller.
+    fn call_runtime(&mut self, procedure: Procedure) -> Result<(), Error> {
+        let runtime = self.runtime.as_mut().unwrap();
+        match procedure {
+            Procedure::Print => {
+                let console = self.mem.console_mut();
+                console.write_str(&format!("{}\n", self.reg[1] as i32))?;
+            }
+            Procedure::Init => runtime.init(self.reg[30]),
+            Procedure::New => self.reg[3] = runtime.alloc(self.reg[1])?,
+            Procedure::Delete => runtime.free(self.reg[1])?,
+        }
+        self.pc = self.reg[31];
+        Ok(())
+    }
+
+    /// Report a signed arithmetic instruction with operands `a` and `b`, if it
+    /// overflowed. Called once the PC has moved past the instruction.
+    fn check_overflow(
+        &mut self,
+        instr: Instr,
+        a: u32,
+        b: u32,
+        overflowed: bool,
+    ) -> Result<(), Error> {
+        let (a, b) = (a as i32, b as i32);
+        match self.overflow {
+            _ if !overflowed => {}
+            Overflow::Wrap => {}
+            Overflow::Warn => self.warn(format!(
+                "Warning! Overflow in {} with operands {} and {} (pc = 0x{:08x})",
+                instr,
+                a,
+                b,
+                self.pc.wrapping_sub(4)
+            )),
+            Overflow::Error => return Err(Error::Overflow { instr, a, b }),
+        }
+        Ok(())
+    }
+
+    /// Execute the instruction at the PC.
+    fn exec(&mut self) -> Result<(), Error> {
+        if self.runtime.is_some() {
+            if let Some(procedure) = Procedure::at(self.pc) {
+                return self.call_runtime(procedure);
+            }
+        }
+
+        let raw = self.mem.load(self.pc)?;
+        let instr = match self.strict_decoding {
+            true => Instr::from_u32_strict(raw),
+            false => Instr::from_u32(raw),
+        };
+        if let Some(isa) = &self.isa {
+            if !isa.allows(&instr) {
+                return Err(Error::DisallowedInstr(instr));
+            }
+        }
         self.pc += 4;
 
         // println!("0x{:08x}: {}", self.pc - 4, instr);
 
         use crate::instr::{I::*, J, R::*};
         match instr {
-            Instr::Inval(_) => return Err(Error::BadInstr),
+            Instr::Inval(_, reason) => return Err(Error::BadInstr(reason)),
             Instr::J { op, i } => match op {
                 J::J => self.pc = i << 2,
                 J::JAL => {
@@ -135,15 +343,19 @@ impl CPU {
                         self.pc = self.pc.wrapping_add(i.wrapping_mul(4))
                     }
                 }
-                ADDI => self.reg[t] = self.reg[s].wrapping_add(i),
-                LW => self.reg[t] = self.mem.load(self.reg[s].wrapping_add(i)),
-                SW => self.mem.store(self.reg[s].wrapping_add(i), self.reg[t]),
+                ADDI => {
+                    let overflowed = (self.reg[s] as i32).checked_add(i as i32).is_none();
+                    self.check_overflow(instr, self.reg[s], i, overflowed)?;
+                    self.reg[t] = self.reg[s].wrapping_add(i)
+                }
+                LW => self.reg[t] = self.mem.load(self.reg[s].wrapping_add(i))?,
+                SW => self.mem.store(self.reg[s].wrapping_add(i), self.reg[t])?,
             },
             Instr::R { op, s, t, d } => match op {
                 MFHI => self.reg[d] = self.hi,
                 MFLO => self.reg[d] = self.lo,
                 LIS => {
-                    self.reg[d] = self.mem.load(self.pc);
+                    self.reg[d] = self.mem.load(self.pc)?;
                     self.pc += 4;
                 }
                 JR => self.pc = self.reg[s],
@@ -153,34 +365,47 @@ impl CPU {
                     self.pc = tmp;
                 }
                 MULT => {
-                    let res = (self.reg[s] as i64).wrapping_mul(self.reg[t] as i64);
+                    // casts required to sign extend the operands
+                    let res = (self.reg[s] as i32 as i64) * (self.reg[t] as i32 as i64);
+                    let overflowed = res != res as i32 as i64;
+                    self.check_overflow(instr, self.reg[s], self.reg[t], overflowed)?;
                     self.hi = (res >> 32) as u32;
-                    self.lo = (res >> 00) as u32;
+                    self.lo = res as u32;
                 }
                 MULTU => {
                     let res = (self.reg[s] as u64).wrapping_mul(self.reg[t] as u64);
                     self.hi = (res >> 32) as u32;
-                    self.lo = (res >> 00) as u32;
+                    self.lo = res as u32;
                 }
+                DIV | DIVU if self.reg[t] == 0 => return Err(Error::DivideByZero),
                 DIV => {
-                    self.hi = ((self.reg[s] as i32) % (self.reg[t] as i32)) as u32;
+                    self.hi = (self.reg[s] as i32).wrapping_rem(self.reg[t] as i32) as u32;
                     self.lo = (self.reg[s] as i32).wrapping_div(self.reg[t] as i32) as u32;
                 }
                 DIVU => {
                     self.hi = self.reg[s] % self.reg[t];
                     self.lo = self.reg[s].wrapping_div(self.reg[t]);
                 }
-                ADD => self.reg[d] = self.reg[s].wrapping_add(self.reg[t]),
-                SUB => self.reg[d] = self.reg[s].wrapping_sub(self.reg[t]),
+                ADD => {
+                    let overflowed = (self.reg[s] as i32)
+                        .checked_add(self.reg[t] as i32)
+                        .is_none();
+                    self.check_overflow(instr, self.reg[s], self.reg[t], overflowed)?;
+                    self.reg[d] = self.reg[s].wrapping_add(self.reg[t])
+                }
+                SUB => {
+                    let overflowed = (self.reg[s] as i32)
+                        .checked_sub(self.reg[t] as i32)
+                        .is_none();
+                    self.check_overflow(instr, self.reg[s], self.reg[t], overflowed)?;
+                    self.reg[d] = self.reg[s].wrapping_sub(self.reg[t])
+                }
                 SLT => self.reg[d] = ((self.reg[s] as i32) < (self.reg[t] as i32)) as u32,
                 SLTU => self.reg[d] = (self.reg[s] < self.reg[t]) as u32,
             },
         }
 
-        // Enforce that reg[0] is always 0
-        self.reg[0] = 0;
-
-        Ok(true)
+        Ok(())
     }
 }
 
@@ -204,3 +429,18 @@ impl fmt::Display for CPU {
         write!(f, "{} $pc = 0x{:08x}", res, self.pc)
     }
 }
+
+impl CPU {
+    /// Dump the registers in the same layout as the course's reference VM
+    /// (`mips.twoints` / `mips.array`), so its output can be diffed against
+    /// ours.
+    pub fn reference_dump(&self) -> String {
+        (1..=31)
+            .map(|i| format!("${:02} = 0x{:08x}   ", i, self.reg[i]))
+            .collect::<Vec<_>>()
+            .chunks(4)
+            .map(|chunk| chunk.concat())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
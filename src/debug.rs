@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::str::FromStr;
 
 use crate::cpu;
@@ -8,7 +9,14 @@ pub struct Debugger {
     cpu: cpu::CPU,
     state: State,
     prev_command: Cmd,
-    past_states: Vec<cpu::CPU>,
+    past_states: VecDeque<cpu::CPU>,
+    /// Rows of memory shown either side of `$30` and the PC
+    context: u32,
+    /// Maximum length of `past_states`
+    history: Option<usize>,
+    /// Number of instructions executed so far
+    steps: u64,
+    max_steps: Option<u64>,
 }
 
 /// Debugger commands
@@ -64,14 +72,41 @@ impl Debugger {
             cpu,
             state: State::AcceptCmd,
             prev_command: Cmd::Step,
-            past_states: Vec::new(),
+            past_states: VecDeque::new(),
+            context: 6,
+            history: None,
+            steps: 0,
+            max_steps: None,
         }
     }
 
+    /// Set how many rows of memory are shown either side of `$30` and the PC.
+    pub fn set_context(&mut self, rows: u32) {
+        self.context = rows;
+    }
+
+    /// Limit how many steps can be undone, to bound the memory used by long
+    /// running programs.
+    pub fn set_history(&mut self, steps: usize) {
+        self.history = Some(steps);
+    }
+
+    /// Stop the program once it has executed `steps` instructions.
+    pub fn set_max_steps(&mut self, steps: u64) {
+        self.max_steps = Some(steps);
+    }
+
+    /// Consume the debugger, returning the CPU in its current state.
+    pub fn into_cpu(self) -> cpu::CPU {
+        self.cpu
+    }
+
     /// Dump machine state in a pretty format.
     fn dump_cpu_state(&mut self) {
+        let context = self.context as i32;
+
         // Print Stack RAM
-        let range = -6i32..=6;
+        let range = -context..=context;
 
         eprintln!("  -------------==== Stack ====-------------");
         eprintln!("       ADDR    |     HEX     |     VAL     ");
@@ -82,17 +117,37 @@ impl Debugger {
 
         for addr in range {
             let indicator = if addr == stack_addr { '>' } else { ' ' };
-            let val = self.cpu.peek(addr);
-            eprintln!(
-                "{}  0x{:08x}  | 0x{:08x}  | {}",
-                indicator, addr, val, val as i32
-            );
+            match self.cpu.bus().device_at(addr) {
+                None => {
+                    let val = self.cpu.peek(addr);
+                    eprintln!(
+                        "{}  0x{:08x}  | 0x{:08x}  | {}",
+                        indicator, addr, val, val as i32
+                    );
+                }
+                Some(device) => match device.peek(addr) {
+                    Some(val) => eprintln!(
+                        "{}  0x{:08x}  | 0x{:08x}  | {} (MMIO: {})",
+                        indicator,
+                        addr,
+                        val,
+                        val as i32,
+                        device.name()
+                    ),
+                    None => eprintln!(
+                        "{}  0x{:08x}  |   no data   | (MMIO: {})",
+                        indicator,
+                        addr,
+                        device.name()
+                    ),
+                },
+            }
         }
 
         eprintln!();
 
         // Print Program RAM
-        let range = -6i32..=6;
+        let range = -context..=context;
 
         eprintln!("  ---------====== Program RAM ======--------");
         eprintln!("     ADDR    |   HEXVAL   :     MIPS ASM    ");
@@ -103,14 +158,33 @@ impl Debugger {
 
         for addr in range {
             let indicator = if addr == pc { '>' } else { ' ' };
-            let val = self.cpu.peek(addr);
-            eprintln!(
-                "{} 0x{:08x} | 0x{:08x} : {}",
-                indicator,
-                addr,
-                val,
-                instr::Instr::from_u32(val)
-            );
+            match self.cpu.bus().device_at(addr) {
+                None => {
+                    let val = self.cpu.peek(addr);
+                    eprintln!(
+                        "{} 0x{:08x} | 0x{:08x} : {}",
+                        indicator,
+                        addr,
+                        val,
+                        instr::Instr::from_u32(val)
+                    );
+                }
+                Some(device) => match device.peek(addr) {
+                    Some(val) => eprintln!(
+                        "{} 0x{:08x} | 0x{:08x} : (MMIO: {})",
+                        indicator,
+                        addr,
+                        val,
+                        device.name()
+                    ),
+                    None => eprintln!(
+                        "{} 0x{:08x} |  no data   : (MMIO: {})",
+                        indicator,
+                        addr,
+                        device.name()
+                    ),
+                },
+            }
         }
 
         eprintln!();
@@ -123,10 +197,48 @@ impl Debugger {
     }
 
     fn step_cpu(&mut self) -> Result<(), String> {
+        if self.cpu.finished() {
+            self.state = State::Done;
+            return Ok(());
+        }
+        if let Some(max_steps) = self.max_steps {
+            if self.steps >= max_steps {
+                eprintln!("Reached the step limit of {} instructions", max_steps);
+                self.state = State::AcceptCmd;
+                return Ok(());
+            }
+        }
+
         let prev_cpu = self.cpu.clone();
-        self.past_states.push(prev_cpu);
+        self.past_states.push_back(prev_cpu);
+        if let Some(history) = self.history {
+            if self.past_states.len() > history {
+                self.past_states.pop_front();
+            }
+        }
+
+        let res = self.cpu.step();
+        self.steps += 1;
+        for warning in self.cpu.take_warnings() {
+            eprintln!("{}", warning);
+        }
+
+        // Program output is interleaved with the debugger's, so it can't sit
+        // in the console's buffer. This also keeps it out of `past_states`,
+        // so stepping backwards never writes the same output twice.
+        self.cpu
+            .bus_mut()
+            .console_mut()
+            .flush()
+            .map_err(|e| format!("CPU Error: {:?}", e))?;
 
-        let keep_running = self.cpu.step().map_err(|e| format!("CPU Error: {:?}", e))?;
+        let keep_running = res.map_err(|e| {
+            format!(
+                "CPU Error: {:?} (pc = 0x{:08x})",
+                e,
+                self.cpu.get_reg(cpu::Reg::PC).unwrap()
+            )
+        })?;
         if !keep_running {
             self.state = State::Done;
         }
@@ -143,8 +255,9 @@ impl Debugger {
             }
             Cmd::StepBackwards => {
                 // Retrieve previous CPU state
-                if let Some(prev_cpu) = self.past_states.pop() {
+                if let Some(prev_cpu) = self.past_states.pop_back() {
                     self.cpu = prev_cpu;
+                    self.steps -= 1;
                 }
                 self.dump_cpu_state();
             }
@@ -174,15 +287,19 @@ impl Debugger {
                     eprint!("{:?}> ", self.prev_command);
 
                     let mut cmd = String::new();
-                    std::io::stdin()
+                    let n = std::io::stdin()
                         .read_line(&mut cmd)
                         .map_err(|_| "Failed to read next command")?;
+                    if n == 0 {
+                        return Err("Reached end of input while waiting for a command".into());
+                    }
 
-                    let cmd = match cmd[..cmd.len() - 1].parse::<Cmd>() {
+                    let cmd = cmd.trim();
+                    let cmd = match cmd.parse::<Cmd>() {
                         Ok(cmd) => cmd,
                         Err(_) => {
-                            if cmd == "\n" {
-                                self.prev_command.clone()
+                            if cmd.is_empty() {
+                                self.prev_command
                             } else {
                                 eprintln!("Invalid commmand.");
                                 continue;
@@ -0,0 +1,48 @@
+use std::fmt::Debug;
+
+use crate::cpu::Error;
+use crate::mem::MEM;
+
+/// A memory-mapped I/O device, which handles CPU accesses to some set of
+/// addresses instead of main memory.
+pub trait Device: DeviceClone + Debug + Send {
+    /// Short human readable name, shown in the debugger.
+    fn name(&self) -> &'static str;
+
+    /// Check if the device handles accesses to `addr`.
+    fn maps(&self, addr: u32) -> bool;
+
+    /// Reads a value from a specified `addr` without side-effects.
+    /// Returns `None` if there's nothing meaningful to show without
+    /// disturbing the device (e.g: input which hasn't arrived yet).
+    fn peek(&self, addr: u32) -> Option<u32>;
+
+    /// Read a value from a specified `addr`
+    /// `mem` is main memory, for devices which work with buffers in RAM.
+    fn load(&mut self, addr: u32, mem: &mut MEM) -> Result<u32, Error>;
+
+    /// Write a value `val` into a specified `addr`
+    /// `mem` is main memory, for devices which work with buffers in RAM.
+    fn store(&mut self, addr: u32, val: u32, mem: &mut MEM) -> Result<(), Error>;
+
+    /// Called once after every instruction the CPU executes.
+    fn tick(&mut self) {}
+}
+
+/// Helper trait to make `Box<dyn Device>` cloneable, so that the debugger can
+/// snapshot the whole machine.
+pub trait DeviceClone {
+    fn box_clone(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone + 'static> DeviceClone for T {
+    fn box_clone(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Box<dyn Device> {
+        self.box_clone()
+    }
+}
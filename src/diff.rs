@@ -0,0 +1,57 @@
+/// How many bytes on either side of a mismatch to show in a diff report.
+const CONTEXT: usize = 16;
+
+/// Compare some `actual` output against the `expected` output.
+/// Returns `None` if they're identical, or a human readable report of the
+/// first mismatching byte otherwise.
+pub fn diff_bytes(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let offset = match expected.iter().zip(actual).position(|(e, a)| e != a) {
+        Some(offset) => offset,
+        None if expected.len() == actual.len() => return None,
+        None => expected.len().min(actual.len()),
+    };
+
+    let line = expected[..offset].iter().filter(|&&b| b == b'\n').count() + 1;
+    let col = offset
+        - expected[..offset]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1)
+        + 1;
+
+    Some(format!(
+        "Output differs at byte {} (line {}, column {})\n  \
+         expected: {} ({} bytes total)\n  \
+         actual:   {} ({} bytes total)\n  \
+         expected context: {}\n  \
+         actual context:   {}",
+        offset,
+        line,
+        col,
+        describe_byte(expected.get(offset)),
+        expected.len(),
+        describe_byte(actual.get(offset)),
+        actual.len(),
+        context(expected, offset),
+        context(actual, offset),
+    ))
+}
+
+fn describe_byte(b: Option<&u8>) -> String {
+    match b {
+        Some(&b) => format!("0x{:02x} {:?}", b, b as char),
+        None => "<end of output>".to_string(),
+    }
+}
+
+fn context(bytes: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(CONTEXT);
+    let end = (offset + CONTEXT).min(bytes.len());
+    let snippet = bytes.get(start..end).unwrap_or(&[]);
+    format!(
+        "{}\"{}\"{}",
+        if start > 0 { "..." } else { "" },
+        snippet.escape_ascii(),
+        if end < bytes.len() { "..." } else { "" }
+    )
+}
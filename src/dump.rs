@@ -0,0 +1,77 @@
+//! `--dump-json`: the machine's final state, in a form other tools can read
+//! without scraping the register dump. The schema is documented in the README,
+//! and `version` is bumped whenever it changes incompatibly.
+
+use crate::cli::Exit;
+use crate::cpu::{Reg, CPU};
+
+/// Version of the schema written by [to_json]
+pub const VERSION: u32 = 1;
+
+/// Quote a string for JSON. Each byte becomes a single character, so output
+/// which isn't valid UTF-8 survives the trip.
+fn string(bytes: &[u8]) -> String {
+    let mut s = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'"' => s += "\\\"",
+            b'\\' => s += "\\\\",
+            b'\n' => s += "\\n",
+            b'\t' => s += "\\t",
+            b'\r' => s += "\\r",
+            0x20..=0x7e => s.push(b as char),
+            _ => s += &format!("\\u{:04x}", b),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Describe the state `cpu` was left in after running `steps` instructions,
+/// along with the words in each of the `(address, length)` memory ranges.
+pub fn to_json(cpu: &CPU, exit: &Exit, steps: u64, ranges: &[(u32, u32)]) -> String {
+    let reg = |reg| cpu.get_reg(reg).unwrap();
+    let pc = reg(Reg::PC);
+
+    let exit = match exit {
+        Exit::Completed => r#"{"reason": "completed"}"#.to_string(),
+        Exit::Error(err) => format!(
+            r#"{{"reason": "error", "error": {}, "message": {}, "pc": {}}}"#,
+            string(err.name().as_bytes()),
+            string(format!("{:?}", err).as_bytes()),
+            pc
+        ),
+        Exit::StepLimit => r#"{"reason": "step-limit"}"#.to_string(),
+    };
+    let registers: Vec<String> = (0..32).map(|i| reg(Reg::Reg(i)).to_string()).collect();
+    let output = cpu.bus().console().captured().unwrap_or_default();
+    let memory: Vec<String> = ranges
+        .iter()
+        .map(|&(addr, len)| {
+            let words: Vec<String> = (0..len)
+                .map(|i| cpu.peek(addr.wrapping_add(i * 4)).to_string())
+                .collect();
+            format!(
+                r#"    {{"address": {}, "words": [{}]}}"#,
+                addr,
+                words.join(", ")
+            )
+        })
+        .collect();
+
+    let mut json = String::from("{\n");
+    json += &format!("  \"version\": {},\n", VERSION);
+    json += &format!("  \"exit\": {},\n", exit);
+    json += &format!("  \"steps\": {},\n", steps);
+    json += &format!("  \"pc\": {},\n", pc);
+    json += &format!("  \"hi\": {},\n", reg(Reg::Hi));
+    json += &format!("  \"lo\": {},\n", reg(Reg::Lo));
+    json += &format!("  \"registers\": [{}],\n", registers.join(", "));
+    json += &format!("  \"output\": {},\n", string(output));
+    match memory.is_empty() {
+        true => json += "  \"memory\": []\n",
+        false => json += &format!("  \"memory\": [\n{}\n  ]\n", memory.join(",\n")),
+    }
+    json += "}\n";
+    json
+}
@@ -0,0 +1,286 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::cpu::Error;
+use crate::device::Device;
+use crate::mem::MEM;
+
+/// Writes set the address of the path to open: a NUL terminated string, one
+/// char per word, relative to the sandbox directory.
+pub const PATH_ADDR: u32 = 0xffff0400;
+/// Writes set the mode to open the file in (see [Mode]).
+pub const MODE_ADDR: u32 = 0xffff0404;
+/// Reads open the file at the current path and mode, returning a handle, or -1
+/// if it couldn't be opened.
+pub const OPEN_ADDR: u32 = 0xffff0408;
+/// Writes select which handle reads and writes of `DATA_ADDR` operate on.
+pub const HANDLE_ADDR: u32 = 0xffff040c;
+/// Reads return the next byte from the selected file, or -1 at EOF.
+/// Writes append a byte to the selected file.
+pub const DATA_ADDR: u32 = 0xffff0410;
+/// Writes close the given handle.
+pub const CLOSE_ADDR: u32 = 0xffff0414;
+/// Reads return the [Status] of the last operation.
+pub const STATUS_ADDR: u32 = 0xffff0418;
+
+/// Paths longer than this are rejected, in case a program forgot the NUL.
+const MAX_PATH_LEN: u32 = 4096;
+
+/// Modes a file can be opened in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    Read = 0,
+    /// Create the file if needed, and truncate it
+    Write = 1,
+    /// Create the file if needed, and write to the end of it
+    Append = 2,
+}
+
+/// Result of the last file operation, as read from `STATUS_ADDR`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    /// The path was invalid, or pointed outside the sandbox
+    BadPath = 1,
+    /// The mode wasn't one of [Mode]
+    BadMode = 2,
+    /// The handle isn't open, or doesn't support the operation
+    BadHandle = 3,
+    /// The host OS reported an error
+    IoError = 4,
+}
+
+#[derive(Clone, Debug)]
+enum Handle {
+    /// Files opened for reading are read into memory up front, so that the
+    /// debugger can step backwards over reads.
+    Read { data: Arc<[u8]>, pos: usize },
+    /// Writes go straight to disk.
+    Write(Arc<Mutex<File>>),
+}
+
+/// Memory-mapped access to files on the host, restricted to a single sandbox
+/// directory.
+#[derive(Clone, Debug)]
+pub struct Files {
+    sandbox: PathBuf,
+    path_addr: u32,
+    mode: u32,
+    handle: u32,
+    handles: Vec<Option<Handle>>,
+    status: Status,
+}
+
+impl Files {
+    /// Create a new Files device, which only allows access to files within
+    /// `sandbox`.
+    pub fn new(sandbox: impl AsRef<Path>) -> std::io::Result<Files> {
+        Ok(Files {
+            sandbox: sandbox.as_ref().canonicalize()?,
+            path_addr: 0,
+            mode: 0,
+            handle: 0,
+            handles: Vec::new(),
+            status: Status::Ok,
+        })
+    }
+
+    /// Read the NUL terminated path at `path_addr` out of memory.
+    fn read_path(&self, mem: &MEM) -> Option<String> {
+        let mut path = String::new();
+        for i in 0..MAX_PATH_LEN {
+            match mem.peek(self.path_addr.wrapping_add(i * 4)) {
+                0 => return Some(path),
+                c => path.push(std::char::from_u32(c)?),
+            }
+        }
+        None
+    }
+
+    /// Resolve a path relative to the sandbox, making sure it doesn't escape
+    /// it (either with `..` or through a symlink).
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = Path::new(path);
+        let lexically_ok = path.components().all(|c| match c {
+            Component::Normal(_) | Component::CurDir => true,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => false,
+        });
+        if path.as_os_str().is_empty() || !lexically_ok {
+            return None;
+        }
+
+        let full = self.sandbox.join(path);
+        // the file itself might not exist yet, so check where its parent
+        // directory really is
+        let parent = full.parent()?.canonicalize().ok()?;
+        if !parent.starts_with(&self.sandbox) {
+            return None;
+        }
+        let full = parent.join(full.file_name()?);
+        // a symlink has to lead somewhere inside the sandbox. A dangling one
+        // would be followed (and its target created) when opening for writing.
+        match full.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let real = full.canonicalize().ok()?;
+                match real.starts_with(&self.sandbox) {
+                    true => Some(real),
+                    false => None,
+                }
+            }
+            _ => Some(full),
+        }
+    }
+
+    fn open(&mut self, mem: &MEM) -> Result<u32, Status> {
+        let mode = match self.mode {
+            0 => Mode::Read,
+            1 => Mode::Write,
+            2 => Mode::Append,
+            _ => return Err(Status::BadMode),
+        };
+        let path = self.read_path(mem).ok_or(Status::BadPath)?;
+        let path = self.resolve(&path).ok_or(Status::BadPath)?;
+
+        let handle = match mode {
+            Mode::Read => Handle::Read {
+                data: std::fs::read(path).map_err(|_| Status::IoError)?.into(),
+                pos: 0,
+            },
+            Mode::Write | Mode::Append => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(mode == Mode::Write)
+                    .append(mode == Mode::Append)
+                    .open(path)
+                    .map_err(|_| Status::IoError)?;
+                Handle::Write(Arc::new(Mutex::new(file)))
+            }
+        };
+
+        // reuse the lowest closed handle
+        match self.handles.iter().position(|h| h.is_none()) {
+            Some(i) => {
+                self.handles[i] = Some(handle);
+                Ok(i as u32)
+            }
+            None => {
+                self.handles.push(Some(handle));
+                Ok(self.handles.len() as u32 - 1)
+            }
+        }
+    }
+
+    fn selected(&mut self) -> Result<&mut Handle, Status> {
+        match self.handles.get_mut(self.handle as usize) {
+            Some(Some(handle)) => Ok(handle),
+            _ => Err(Status::BadHandle),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u32, Status> {
+        match self.selected()? {
+            Handle::Read { data, pos } => match data.get(*pos) {
+                Some(&b) => {
+                    *pos += 1;
+                    Ok(b as u32)
+                }
+                None => Ok(0xffffffff),
+            },
+            Handle::Write(_) => Err(Status::BadHandle),
+        }
+    }
+
+    fn write_byte(&mut self, val: u32) -> Result<(), Status> {
+        match self.selected()? {
+            Handle::Write(file) => file
+                .lock()
+                .unwrap()
+                .write_all(&[val as u8])
+                .map_err(|_| Status::IoError),
+            Handle::Read { .. } => Err(Status::BadHandle),
+        }
+    }
+
+    fn close(&mut self, handle: u32) -> Result<(), Status> {
+        match self.handles.get_mut(handle as usize) {
+            Some(h @ Some(_)) => {
+                *h = None;
+                Ok(())
+            }
+            _ => Err(Status::BadHandle),
+        }
+    }
+}
+
+impl Device for Files {
+    fn name(&self) -> &'static str {
+        "files"
+    }
+
+    fn maps(&self, addr: u32) -> bool {
+        matches!(
+            addr,
+            PATH_ADDR | MODE_ADDR | OPEN_ADDR | HANDLE_ADDR | DATA_ADDR | CLOSE_ADDR | STATUS_ADDR
+        )
+    }
+
+    fn peek(&self, addr: u32) -> Option<u32> {
+        match addr {
+            PATH_ADDR => Some(self.path_addr),
+            MODE_ADDR => Some(self.mode),
+            HANDLE_ADDR => Some(self.handle),
+            DATA_ADDR => match self.handles.get(self.handle as usize) {
+                Some(Some(Handle::Read { data, pos })) => {
+                    Some(data.get(*pos).map_or(0xffffffff, |&b| b as u32))
+                }
+                _ => None,
+            },
+            STATUS_ADDR => Some(self.status as u32),
+            // reading OPEN_ADDR opens a file, and CLOSE_ADDR is write-only
+            _ => None,
+        }
+    }
+
+    fn load(&mut self, addr: u32, mem: &mut MEM) -> Result<u32, Error> {
+        let res = match addr {
+            OPEN_ADDR => self.open(mem),
+            DATA_ADDR => self.read_byte(),
+            addr => return Ok(self.peek(addr).unwrap_or(0)),
+        };
+        match res {
+            Ok(val) => {
+                self.status = Status::Ok;
+                Ok(val)
+            }
+            Err(status) => {
+                self.status = status;
+                Ok(0xffffffff)
+            }
+        }
+    }
+
+    fn store(&mut self, addr: u32, val: u32, _mem: &mut MEM) -> Result<(), Error> {
+        let res = match addr {
+            PATH_ADDR => {
+                self.path_addr = val;
+                return Ok(());
+            }
+            MODE_ADDR => {
+                self.mode = val;
+                return Ok(());
+            }
+            HANDLE_ADDR => {
+                self.handle = val;
+                return Ok(());
+            }
+            DATA_ADDR => self.write_byte(val),
+            CLOSE_ADDR => self.close(val),
+            _ => return Ok(()),
+        };
+        self.status = res.err().unwrap_or(Status::Ok);
+        Ok(())
+    }
+}
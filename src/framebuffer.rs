@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::cpu::Error;
+use crate::device::Device;
+use crate::mem::MEM;
+
+/// Pixels are stored one word per pixel (as `0x00RRGGBB`) in row-major order,
+/// starting at this address.
+pub const PIXELS_ADDR: u32 = 0xe0000000;
+/// Writing any value to this address dumps the current frame to disk. Reads
+/// return the number of frames dumped so far.
+pub const DUMP_ADDR: u32 = 0xffff0100;
+/// Reads return the width of the framebuffer.
+pub const WIDTH_ADDR: u32 = 0xffff0104;
+/// Reads return the height of the framebuffer.
+pub const HEIGHT_ADDR: u32 = 0xffff0108;
+
+/// Memory-mapped framebuffer.
+/// Frames are dumped as numbered PPM files (`frame_0000.ppm`,
+/// `frame_0001.ppm`, ...) in the output directory.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u32>,
+    dir: PathBuf,
+    frames: u32,
+}
+
+impl Framebuffer {
+    /// Create a new, all black, `width` x `height` framebuffer, which dumps
+    /// frames into `dir`.
+    pub fn new(width: u32, height: u32, dir: impl Into<PathBuf>) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+            dir: dir.into(),
+            frames: 0,
+        }
+    }
+
+    /// Index into `pixels` for a given address, if it's in the framebuffer.
+    fn pixel_index(&self, addr: u32) -> Option<usize> {
+        let offset = addr.checked_sub(PIXELS_ADDR)?;
+        if offset % 4 != 0 {
+            return None;
+        }
+        let i = (offset / 4) as usize;
+        if i < self.pixels.len() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// Write the current frame to the next numbered PPM file.
+    fn dump(&mut self) -> std::io::Result<()> {
+        let path = self.dir.join(format!("frame_{:04}.ppm", self.frames));
+        let mut f = BufWriter::new(File::create(path)?);
+        write!(f, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for px in &self.pixels {
+            f.write_all(&[(px >> 16) as u8, (px >> 8) as u8, *px as u8])?;
+        }
+        f.flush()?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+impl Device for Framebuffer {
+    fn name(&self) -> &'static str {
+        "framebuffer"
+    }
+
+    fn maps(&self, addr: u32) -> bool {
+        match addr {
+            DUMP_ADDR | WIDTH_ADDR | HEIGHT_ADDR => true,
+            addr => self.pixel_index(addr).is_some(),
+        }
+    }
+
+    fn peek(&self, addr: u32) -> Option<u32> {
+        match addr {
+            DUMP_ADDR => Some(self.frames),
+            WIDTH_ADDR => Some(self.width),
+            HEIGHT_ADDR => Some(self.height),
+            addr => self.pixel_index(addr).map(|i| self.pixels[i]),
+        }
+    }
+
+    fn load(&mut self, addr: u32, _mem: &mut MEM) -> Result<u32, Error> {
+        Ok(self.peek(addr).unwrap_or(0))
+    }
+
+    fn store(&mut self, addr: u32, val: u32, _mem: &mut MEM) -> Result<(), Error> {
+        match addr {
+            DUMP_ADDR => self.dump().map_err(Error::FramebufferDump)?,
+            WIDTH_ADDR | HEIGHT_ADDR => {}
+            addr => {
+                if let Some(i) = self.pixel_index(addr) {
+                    self.pixels[i] = val;
+                }
+            }
+        }
+        Ok(())
+    }
+}
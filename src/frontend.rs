@@ -0,0 +1,508 @@
+//! Frontends are the different ways of passing arguments to a program,
+//! selected by name on the command line (e.g: `uwmips twoints prog.mips`).
+
+use std::io::BufRead;
+use std::ops::Range;
+
+use crate::cpu::{Reg, CPU};
+
+/// Frontend used when none is given on the command line.
+pub const DEFAULT: &str = "noargs";
+
+/// A command line option.
+pub struct Opt {
+    pub name: &'static str,
+    /// Name of the option's value, if it takes one
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+/// A way of passing arguments to a program.
+///
+/// A new frontend is created from the [Registry] for each run. Any of its
+/// [options](Frontend::options) given on the command line are passed to
+/// `set_option`, then it parses its positional arguments, and finally `init`
+/// sets up the CPU once the program has been loaded.
+///
+/// Frontends need to be `Send`, since `uwmips test` sets up each case's
+/// machine on a worker thread.
+pub trait Frontend: Send {
+    /// Name used to select the frontend on the command line
+    fn name(&self) -> &'static str;
+
+    /// Describes the frontend's arguments, for the usage text
+    fn usage(&self) -> &'static str;
+
+    /// Extra command line options the frontend accepts
+    fn options(&self) -> &'static [Opt] {
+        &[]
+    }
+
+    /// Handle one of the frontend's `options`
+    fn set_option(&mut self, _name: &str, _value: Option<String>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether `parse` will prompt for the arguments on stdin, given the
+    /// options set so far
+    fn prompts(&self) -> bool {
+        false
+    }
+
+    /// Parse the frontend's arguments from those following the filename,
+    /// leaving any it doesn't need.
+    fn parse(&mut self, args: &mut dyn Iterator<Item = String>) -> Result<(), String>;
+
+    /// Set up the CPU to run the program, once it's been loaded.
+    fn init(&mut self, cpu: &mut CPU, layout: &mut Layout) -> Result<(), String>;
+}
+
+/// Creates a new instance of a frontend.
+pub type Constructor = fn() -> Box<dyn Frontend>;
+
+/// The set of frontends which can be selected on the command line.
+#[derive(Clone)]
+pub struct Registry {
+    frontends: Vec<Constructor>,
+}
+
+impl Registry {
+    /// Create a new Registry without any frontends.
+    pub fn new() -> Registry {
+        Registry {
+            frontends: Vec::new(),
+        }
+    }
+
+    /// Add a frontend, replacing any existing frontend with the same name.
+    pub fn register(&mut self, new: Constructor) {
+        let name = new().name();
+        self.frontends.retain(|f| f().name() != name);
+        self.frontends.push(new);
+    }
+
+    /// Create a new instance of the frontend called `name`.
+    pub fn create(&self, name: &str) -> Option<Box<dyn Frontend>> {
+        self.all().into_iter().find(|f| f.name() == name)
+    }
+
+    /// Create an instance of every frontend, in the order they were added.
+    pub fn all(&self) -> Vec<Box<dyn Frontend>> {
+        self.frontends.iter().map(|new| new()).collect()
+    }
+}
+
+impl Default for Registry {
+    /// The built-in frontends.
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register(|| Box::new(NoArgs));
+        registry.register(|| Box::new(TwoInts::prompt()));
+        registry.register(|| Box::new(TwoInts::args()));
+        registry.register(|| Box::new(Array::prompt()));
+        registry.register(|| Box::new(Array::args()));
+        registry.register(|| Box::new(StringArg::default()));
+        registry.register(|| Box::new(Argv::default()));
+        registry
+    }
+}
+
+/// Where things are in memory, for frontends to put their data around.
+pub struct Layout {
+    /// Filename of the program
+    pub filename: String,
+    /// Addresses the program was loaded at
+    pub program: Range<u64>,
+    /// Where frontend data goes: right after the program (like the course VM)
+    /// unless given on the command line
+    pub data_base: u32,
+    data: Option<(&'static str, Range<u64>)>,
+}
+
+impl Layout {
+    /// Create a new Layout, for a program loaded at `program`.
+    pub fn new(filename: String, program: Range<u64>, data_base: u32) -> Layout {
+        Layout {
+            filename,
+            program,
+            data_base,
+            data: None,
+        }
+    }
+
+    /// Store `words` at `data_base`, returning their address.
+    /// Fails if they don't fit in memory, or would overlap the program or any
+    /// memory-mapped devices. `what` describes the data in messages.
+    pub fn place_data(
+        &mut self,
+        cpu: &mut CPU,
+        what: &'static str,
+        words: &[u32],
+    ) -> Result<u32, String> {
+        let base = self.data_base;
+        let end = base as u64 + words.len() as u64 * 4;
+        if end > 1 << 32 {
+            return Err(format!(
+                "The {} ({} words) doesn't fit in memory at 0x{:08x}",
+                what,
+                words.len(),
+                base
+            ));
+        }
+
+        if !words.is_empty() {
+            if (base as u64) < self.program.end && end > self.program.start {
+                return Err(format!(
+                    "The {} (0x{:08x}..0x{:08x}) overlaps the program (0x{:08x}..0x{:08x})",
+                    what, base, end, self.program.start, self.program.end
+                ));
+            }
+            for addr in (base as u64..end).step_by(4) {
+                if let Some(device) = cpu.bus().device_at(addr as u32) {
+                    return Err(format!(
+                        "The {} overlaps the {} at 0x{:08x}",
+                        what,
+                        device.name(),
+                        addr
+                    ));
+                }
+            }
+        }
+
+        for (i, word) in words.iter().enumerate() {
+            let _ = cpu.store(base + (i as u32) * 4, *word);
+        }
+        self.data = Some((what, base as u64..end));
+        Ok(base)
+    }
+
+    /// The data placed by the frontend (if any), and what it was.
+    pub fn data(&self) -> Option<(&'static str, Range<u64>)> {
+        self.data.clone()
+    }
+}
+
+// -------------------------------- Helpers -------------------------------- //
+
+/// Parse a decimal or `0x` prefixed hex integer, which may be negative.
+pub fn parse_int(s: &str) -> Option<i64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let (radix, digits) = match digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        Some(digits) => (16, digits),
+        None => (10, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    let n = i64::from_str_radix(digits, radix).ok()?;
+    Some(if negative { -n } else { n })
+}
+
+/// Parse a value for a 32 bit register, given either signed or unsigned.
+/// `what` describes the value in errors.
+pub fn parse_i32(s: &str, what: &str) -> Result<i32, String> {
+    match parse_int(s.trim()) {
+        Some(n) if (i32::MIN as i64..=u32::MAX as i64).contains(&n) => Ok(n as i32),
+        Some(_) => Err(format!("{} \"{}\" doesn't fit in 32 bits", what, s.trim())),
+        None => Err(format!("{} \"{}\" isn't an integer", what, s.trim())),
+    }
+}
+
+/// Prompt for a line on stdin, returning it without surrounding whitespace.
+pub fn prompt(msg: &str) -> Result<String, String> {
+    eprint!("{}", msg);
+    let mut buf = String::new();
+    match std::io::stdin().lock().read_line(&mut buf) {
+        Ok(0) => Err(format!("Unexpected end of input at \"{}\"", msg.trim())),
+        Ok(_) => Ok(buf.trim().to_string()),
+        Err(e) => Err(format!("Failed to read from stdin: {}", e)),
+    }
+}
+
+/// A NUL terminated string, one char per word.
+pub fn string_words(s: &str) -> Vec<u32> {
+    s.chars()
+        .map(|c| c as u32)
+        .chain(std::iter::once(0))
+        .collect()
+}
+
+// ------------------------------- Frontends ------------------------------- //
+
+/// Doesn't pass anything to the program.
+pub struct NoArgs;
+
+impl Frontend for NoArgs {
+    fn name(&self) -> &'static str {
+        "noargs"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<no args> (default)"
+    }
+
+    fn parse(&mut self, _args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn init(&mut self, _cpu: &mut CPU, _layout: &mut Layout) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Passes two integers in `$1` and `$2`.
+pub struct TwoInts {
+    /// Whether the integers come from the command line, or are prompted for
+    from_args: bool,
+    ints: [i32; 2],
+}
+
+impl TwoInts {
+    /// `twoints`, which prompts for the integers on stdin
+    pub fn prompt() -> TwoInts {
+        TwoInts {
+            from_args: false,
+            ints: [0; 2],
+        }
+    }
+
+    /// `twointsargs`, which takes the integers on the command line
+    pub fn args() -> TwoInts {
+        TwoInts {
+            from_args: true,
+            ints: [0; 2],
+        }
+    }
+}
+
+impl Frontend for TwoInts {
+    fn name(&self) -> &'static str {
+        match self.from_args {
+            false => "twoints",
+            true => "twointsargs",
+        }
+    }
+
+    fn usage(&self) -> &'static str {
+        match self.from_args {
+            false => "<no args>, prompts for $1 and $2",
+            true => "<int1> <int2>",
+        }
+    }
+
+    fn prompts(&self) -> bool {
+        !self.from_args
+    }
+
+    fn parse(&mut self, args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+        for (i, int) in self.ints.iter_mut().enumerate() {
+            *int = if self.from_args {
+                let what = format!("int{}", i + 1);
+                let arg = args
+                    .next()
+                    .ok_or_else(|| format!("{} not specified", what))?;
+                parse_i32(&arg, &what)?
+            } else {
+                let line = prompt(&format!("Enter value for register {}: ", i + 1))?;
+                parse_i32(&line, &format!("Register {} value", i + 1))?
+            };
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, cpu: &mut CPU, _layout: &mut Layout) -> Result<(), String> {
+        let _ = cpu.set_reg(Reg::Reg(1), self.ints[0] as u32);
+        let _ = cpu.set_reg(Reg::Reg(2), self.ints[1] as u32);
+        Ok(())
+    }
+}
+
+/// Passes an array of integers, with its address in `$1` and its length in
+/// `$2`.
+pub struct Array {
+    /// Whether the elements come from the command line, or are prompted for
+    from_args: bool,
+    /// File to read the elements from instead of prompting
+    file: Option<String>,
+    array: Vec<i32>,
+}
+
+const ARRAY_OPTIONS: &[Opt] = &[Opt {
+    name: "array-file",
+    value: Some("file"),
+    help: "Read the array for the array frontend from a file",
+}];
+
+impl Array {
+    /// `array`, which prompts for the elements on stdin (or reads them from
+    /// `--array-file`)
+    pub fn prompt() -> Array {
+        Array {
+            from_args: false,
+            file: None,
+            array: Vec::new(),
+        }
+    }
+
+    /// `arrayargs`, which takes the elements on the command line
+    pub fn args() -> Array {
+        Array {
+            from_args: true,
+            file: None,
+            array: Vec::new(),
+        }
+    }
+}
+
+impl Frontend for Array {
+    fn name(&self) -> &'static str {
+        match self.from_args {
+            false => "array",
+            true => "arrayargs",
+        }
+    }
+
+    fn usage(&self) -> &'static str {
+        match self.from_args {
+            false => "<no args>, prompts for the array (unless --array-file is given)",
+            true => "<elements...>",
+        }
+    }
+
+    fn options(&self) -> &'static [Opt] {
+        match self.from_args {
+            false => ARRAY_OPTIONS,
+            true => &[],
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        if name == "array-file" {
+            self.file = value;
+        }
+        Ok(())
+    }
+
+    fn prompts(&self) -> bool {
+        !self.from_args && self.file.is_none()
+    }
+
+    fn parse(&mut self, args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+        let element = |(i, s): (usize, &str)| parse_i32(s, &format!("Array element {}", i));
+
+        if self.from_args {
+            let args: Vec<String> = args.collect();
+            self.array = args
+                .iter()
+                .map(String::as_str)
+                .enumerate()
+                .map(element)
+                .collect::<Result<_, _>>()?;
+        } else if let Some(path) = &self.file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read array file {}: {}", path, e))?;
+            self.array = contents
+                .split_whitespace()
+                .enumerate()
+                .map(element)
+                .collect::<Result<_, _>>()?;
+        } else {
+            let len = prompt("Enter length of array: ")?;
+            let len: u32 = len
+                .parse()
+                .map_err(|_| format!("Array length \"{}\" isn't a valid length", len))?;
+            for i in 0..len {
+                let line = prompt(&format!("Enter array element {}: ", i))?;
+                self.array.push(element((i as usize, &line))?);
+            }
+        }
+        Ok(())
+    }
+
+    fn init(&mut self, cpu: &mut CPU, layout: &mut Layout) -> Result<(), String> {
+        let words: Vec<u32> = self.array.iter().map(|&n| n as u32).collect();
+        let base = layout.place_data(cpu, "array", &words)?;
+        let _ = cpu.set_reg(Reg::Reg(1), base);
+        let _ = cpu.set_reg(Reg::Reg(2), words.len() as u32);
+        Ok(())
+    }
+}
+
+/// Passes a NUL terminated string (one char per word), with its address in
+/// `$1` and its length in `$2`.
+#[derive(Default)]
+pub struct StringArg {
+    string: String,
+}
+
+impl Frontend for StringArg {
+    fn name(&self) -> &'static str {
+        "string"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<string>"
+    }
+
+    fn parse(&mut self, args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+        self.string = args.next().ok_or("string not specified")?;
+        Ok(())
+    }
+
+    fn init(&mut self, cpu: &mut CPU, layout: &mut Layout) -> Result<(), String> {
+        let words = string_words(&self.string);
+        let base = layout.place_data(cpu, "string", &words)?;
+        let _ = cpu.set_reg(Reg::Reg(1), base);
+        let _ = cpu.set_reg(Reg::Reg(2), words.len() as u32 - 1);
+        Ok(())
+    }
+}
+
+/// Passes C-style arguments: `$1` is `argc`, and `$2` is `argv`, an array of
+/// pointers to each argument (followed by a NULL pointer). Like C, the first
+/// argument is the program's filename.
+#[derive(Default)]
+pub struct Argv {
+    args: Vec<String>,
+}
+
+impl Frontend for Argv {
+    fn name(&self) -> &'static str {
+        "argv"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<args...>"
+    }
+
+    fn parse(&mut self, args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+        self.args = args.collect();
+        Ok(())
+    }
+
+    fn init(&mut self, cpu: &mut CPU, layout: &mut Layout) -> Result<(), String> {
+        let argv: Vec<String> = std::iter::once(layout.filename.clone())
+            .chain(self.args.iter().cloned())
+            .collect();
+
+        // the pointers come first, followed by the strings themselves
+        let base = layout.data_base;
+        let strings_base = base.wrapping_add((argv.len() as u32 + 1) * 4);
+        let mut pointers = Vec::new();
+        let mut strings = Vec::new();
+        for arg in &argv {
+            pointers.push(strings_base.wrapping_add(strings.len() as u32 * 4));
+            strings.extend(string_words(arg));
+        }
+        pointers.push(0);
+        pointers.extend(strings);
+
+        layout.place_data(cpu, "argv", &pointers)?;
+        let _ = cpu.set_reg(Reg::Reg(1), argv.len() as u32);
+        let _ = cpu.set_reg(Reg::Reg(2), base);
+        Ok(())
+    }
+}
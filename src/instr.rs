@@ -1,6 +1,9 @@
+// Opcode literals are grouped to line up with the encodings in `ref_notes.md`
+#![allow(clippy::unusual_byte_groupings)]
+
 /// Register mode Opcodes
 /// Bits 0..6 on a R mode instruction
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 #[derive(Copy, Clone)]
 pub enum R {
     MFHI  = 0b_0001_0000,
@@ -20,7 +23,7 @@ pub enum R {
 
 /// Immediate mode Opcodes
 /// Bits 27..31 on a I mode instruction
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 #[derive(Copy, Clone)]
 pub enum I {
     BEQ  = 0b_0001_00,
@@ -32,7 +35,7 @@ pub enum I {
 
 /// Jump mode Opcodes
 /// Bits 27..31 on a J mode instruction
-#[cfg_attr(rustfmt, rustfmt_skip)]
+#[rustfmt::skip]
 #[derive(Copy, Clone)]
 pub enum J {
     J   = 0b_0000_10,
@@ -43,10 +46,24 @@ pub enum J {
 /// Consists of an opcode, and some associated operands.
 #[derive(Copy, Clone)]
 pub enum Instr {
-    J { op: J, i: u32 },
-    I { op: I, s: usize, t: usize, i: u32 },
-    R { op: R, s: usize, t: usize, d: usize },
-    Inval(u32),
+    J {
+        op: J,
+        i: u32,
+    },
+    I {
+        op: I,
+        s: usize,
+        t: usize,
+        i: u32,
+    },
+    R {
+        op: R,
+        s: usize,
+        t: usize,
+        d: usize,
+    },
+    /// A word which isn't a valid instruction, and why
+    Inval(u32, &'static str),
 }
 
 /// Convert raw opcode bits into the associated enum
@@ -112,15 +129,15 @@ impl Instr {
             // R all start with 0000 00
             0 => {
                 let s = (raw >> (20 + 1)) & 0b11111;
-                let t = (raw >> (16 + 0)) & 0b11111;
+                let t = (raw >> 16) & 0b11111;
                 let d = (raw >> (8 + 3)) & 0b11111;
                 let op = match R::from_raw_op((raw & 0b_1111_1111) as u8) {
                     Some(op) => op,
-                    None => return Instr::Inval(raw),
+                    None => return Instr::Inval(raw, "unknown function"),
                 };
 
                 Instr::R {
-                    op: op,
+                    op,
                     s: s as usize,
                     t: t as usize,
                     d: d as usize,
@@ -131,27 +148,24 @@ impl Instr {
                 let i = raw & 0b_11_1111_1111_1111_1111_1111_1111;
                 let op = match J::from_raw_op(op as u8) {
                     Some(op) => op,
-                    None => return Instr::Inval(raw),
+                    None => return Instr::Inval(raw, "unknown opcode"),
                 };
 
-                Instr::J {
-                    op: op,
-                    i: i as u32,
-                }
+                Instr::J { op, i }
             }
             // I start with non-0000
             op => {
                 let s = (raw >> (20 + 1)) & 0b11111;
-                let t = (raw >> (16 + 0)) & 0b11111;
+                let t = (raw >> 16) & 0b11111;
                 // casts required to properly sign extend
                 let i = (raw as i16) as i32;
                 let op = match I::from_raw_op(op as u8) {
                     Some(op) => op,
-                    None => return Instr::Inval(raw),
+                    None => return Instr::Inval(raw, "unknown opcode"),
                 };
 
                 Instr::I {
-                    op: op,
+                    op,
                     s: s as usize,
                     t: t as usize,
                     i: i as u32,
@@ -159,6 +173,41 @@ impl Instr {
             }
         }
     }
+
+    /// Like [Instr::from_u32], but also rejects R mode instructions where
+    /// any of the fields the instruction doesn't use aren't zero, as the
+    /// course VM does (see the encodings in `ref_notes.md`).
+    pub fn from_u32_strict(raw: u32) -> Instr {
+        use R::*;
+        // the bottom of the shift amount overlaps the 8 bits from_u32 matches
+        // the function against, so check it first
+        if raw >> (24 + 2) == 0 && (raw >> 6) & 0b11111 != 0 {
+            return Instr::Inval(raw, "shift amount must be 0");
+        }
+        let instr = Instr::from_u32(raw);
+        let (op, s, t, d) = match instr {
+            Instr::R { op, s, t, d } => (op, s, t, d),
+            _ => return instr,
+        };
+
+        // which of the s, t and d fields the instruction uses
+        let (uses_s, uses_t, uses_d) = match op {
+            MFHI | MFLO | LIS => (false, false, true),
+            JR | JALR => (true, false, false),
+            MULT | MULTU | DIV | DIVU => (true, true, false),
+            ADD | SUB | SLT | SLTU => (true, true, true),
+        };
+        let reason = if !uses_s && s != 0 {
+            "s register must be 0"
+        } else if !uses_t && t != 0 {
+            "t register must be 0"
+        } else if !uses_d && d != 0 {
+            "d register must be 0"
+        } else {
+            return instr;
+        };
+        Instr::Inval(raw, reason)
+    }
 }
 
 // ------------------------- Display Implementations ------------------------ //
@@ -166,6 +215,7 @@ impl Instr {
 use std::fmt;
 
 impl fmt::Display for Instr {
+    #[rustfmt::skip]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use I::*;
         use R::*;
@@ -173,7 +223,6 @@ impl fmt::Display for Instr {
             Instr::J { op, i } => write!(f, "{:<5} 0x{:08x}", op, i),
             Instr::I { op, s, t, i } => {
                 let i = i as i16;
-                #[cfg_attr(rustfmt, rustfmt_skip)]
                 match op {
                     BEQ | BNE  => write!(f, "{:<5} ${}, ${}, {}", op, s, t, i),
                     ADDI       => write!(f, "{:<5} ${}, ${}, {}", op, t, s, i),
@@ -182,7 +231,6 @@ impl fmt::Display for Instr {
             }
             Instr::R { op, s, t, d } =>
             {
-                #[cfg_attr(rustfmt, rustfmt_skip)]
                 match op {
                     MFHI | MFLO  | LIS        => write!(f, "{:<5} ${}", op, d),
                     JR   | JALR               => write!(f, "{:<5} ${}", op, s),
@@ -190,15 +238,21 @@ impl fmt::Display for Instr {
                     ADD  | SUB   | SLT | SLTU => write!(f, "{:<5} ${}, ${}, ${}", op, d, s, t),
                 }
             }
-            Instr::Inval(raw) => write!(f, ".word 0x{:08x} ({})", raw, raw as i32),
+            Instr::Inval(raw, _) => write!(f, ".word 0x{:08x} ({})", raw, raw as i32),
         }
     }
 }
 
+impl fmt::Debug for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
 impl fmt::Display for R {
+    #[rustfmt::skip]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use R::*;
-        #[cfg_attr(rustfmt, rustfmt_skip)]
         match *self {
             MFHI  => write!(f, "mfhi"),
             MFLO  => write!(f, "mflo"),
@@ -218,9 +272,9 @@ impl fmt::Display for R {
 }
 
 impl fmt::Display for I {
+    #[rustfmt::skip]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use I::*;
-        #[cfg_attr(rustfmt, rustfmt_skip)]
         match *self {
             BEQ  => write!(f, "beq"),
             BNE  => write!(f, "bne"),
@@ -232,8 +286,8 @@ impl fmt::Display for I {
 }
 
 impl fmt::Display for J {
+    #[rustfmt::skip]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        #[cfg_attr(rustfmt, rustfmt_skip)]
         match *self {
             J::J   => write!(f, "j"),
             J::JAL => write!(f, "jal"),
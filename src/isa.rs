@@ -0,0 +1,130 @@
+//! ISA profiles, which restrict the instructions a program is allowed to use.
+
+use crate::instr::{Instr, I, J, R};
+
+/// Mnemonics of every instruction the VM can run. A [Profile] has a bit set
+/// for each one it allows, in this order.
+const MNEMONICS: [&str; 20] = [
+    "add", "sub", "mult", "multu", "div", "divu", "mfhi", "mflo", "lis", "lw", "sw", "slt", "sltu",
+    "beq", "bne", "jr", "jalr", "addi", "j", "jal",
+];
+
+/// The instructions accepted by the course's `cs241.binasm`
+const CS241: &[&str] = &[
+    "add", "sub", "mult", "multu", "div", "divu", "mfhi", "mflo", "lis", "lw", "sw", "slt", "sltu",
+    "beq", "bne", "jr", "jalr",
+];
+
+#[rustfmt::skip]
+const PROFILES: &[(&str, &[&str])] = &[
+    ("all",   &MNEMONICS),
+    ("cs241", CS241),
+];
+
+/// Index of an instruction's mnemonic in [MNEMONICS]
+fn index(instr: &Instr) -> Option<usize> {
+    let index = match *instr {
+        Instr::R { op, .. } => match op {
+            R::ADD => 0,
+            R::SUB => 1,
+            R::MULT => 2,
+            R::MULTU => 3,
+            R::DIV => 4,
+            R::DIVU => 5,
+            R::MFHI => 6,
+            R::MFLO => 7,
+            R::LIS => 8,
+            R::SLT => 11,
+            R::SLTU => 12,
+            R::JR => 15,
+            R::JALR => 16,
+        },
+        Instr::I { op, .. } => match op {
+            I::LW => 9,
+            I::SW => 10,
+            I::BEQ => 13,
+            I::BNE => 14,
+            I::ADDI => 17,
+        },
+        Instr::J { op, .. } => match op {
+            J::J => 18,
+            J::JAL => 19,
+        },
+        Instr::Inval(..) => return None,
+    };
+    Some(index)
+}
+
+/// A set of instructions programs are allowed to use.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    /// How the profile was given (e.g: `cs241` or `cs241,-mult`)
+    name: String,
+    /// Bit `n` is set if `MNEMONICS[n]` is allowed
+    allowed: u32,
+}
+
+impl Profile {
+    /// Parse a profile: a comma separated list of profile names (`all` or
+    /// `cs241`) and instruction mnemonics to allow, or mnemonics prefixed
+    /// with `-` to disallow.
+    pub fn parse(s: &str) -> Result<Profile, String> {
+        let bit = |mnemonic: &str| {
+            MNEMONICS
+                .iter()
+                .position(|&m| m == mnemonic)
+                .map(|n| 1 << n)
+                .ok_or_else(|| format!("Unknown instruction or ISA profile \"{}\"", mnemonic))
+        };
+
+        let mut allowed = 0;
+        for item in s.split(',').map(str::trim) {
+            if let Some(mnemonic) = item.strip_prefix('-') {
+                allowed &= !bit(mnemonic)?;
+            } else if let Some((_, mnemonics)) = PROFILES.iter().find(|(name, _)| *name == item) {
+                for mnemonic in mnemonics.iter() {
+                    allowed |= bit(mnemonic)?;
+                }
+            } else {
+                allowed |= bit(item)?;
+            }
+        }
+        Ok(Profile {
+            name: s.to_string(),
+            allowed,
+        })
+    }
+
+    /// How the profile was specified
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Check if the profile allows an instruction. Invalid instructions are
+    /// always "allowed", since they're an error anyway.
+    pub fn allows(&self, instr: &Instr) -> bool {
+        match index(instr) {
+            Some(n) => self.allowed & (1 << n) != 0,
+            None => true,
+        }
+    }
+}
+
+/// Find every instruction in a program which `profile` doesn't allow,
+/// returning each one's offset (in words) from the start of the program.
+///
+/// Words loaded by `lis` are skipped, since they're almost always constants.
+/// Other data (e.g: `.word` tables) can still be reported, if it happens to
+/// look like a disallowed instruction.
+pub fn scan(program: &[u32], profile: &Profile) -> Vec<(usize, Instr)> {
+    let mut disallowed = Vec::new();
+    let mut after_lis = false;
+    for (i, &word) in program.iter().enumerate() {
+        let instr = Instr::from_u32(word);
+        if !after_lis && !profile.allows(&instr) {
+            disallowed.push((i, instr));
+        }
+        after_lis = !after_lis && matches!(instr, Instr::R { op: R::LIS, .. });
+    }
+    disallowed
+}
@@ -0,0 +1,59 @@
+//! `uwmips` - A simulator for the MIPS instruction set used in CS241 and CS230
+//! at the University of Waterloo.
+//!
+//! Besides the `uwmips` binary, the VM can be used as a library. For example,
+//! a course with its own way of passing arguments to programs can implement
+//! [frontend::Frontend], and run the full command line interface with it:
+//!
+//! ```no_run
+//! use uwmips::cpu::{Reg, CPU};
+//! use uwmips::frontend::{Frontend, Layout, Registry};
+//!
+//! /// Passes the answer to everything in `$1`
+//! struct Answer;
+//!
+//! impl Frontend for Answer {
+//!     fn name(&self) -> &'static str {
+//!         "answer"
+//!     }
+//!
+//!     fn usage(&self) -> &'static str {
+//!         "<no args>"
+//!     }
+//!
+//!     fn parse(&mut self, _args: &mut dyn Iterator<Item = String>) -> Result<(), String> {
+//!         Ok(())
+//!     }
+//!
+//!     fn init(&mut self, cpu: &mut CPU, _layout: &mut Layout) -> Result<(), String> {
+//!         cpu.set_reg(Reg::Reg(1), 42).map_err(|e| format!("{:?}", e))
+//!     }
+//! }
+//!
+//! let mut frontends = Registry::default();
+//! frontends.register(|| Box::new(Answer));
+//! uwmips::cli::main(frontends);
+//! ```
+
+mod args;
+pub mod asm;
+pub mod bus;
+pub mod cli;
+mod config;
+pub mod console;
+pub mod cpu;
+pub mod debug;
+pub mod device;
+mod diff;
+mod dump;
+pub mod files;
+pub mod framebuffer;
+pub mod frontend;
+pub mod instr;
+pub mod isa;
+pub mod mem;
+pub mod merl;
+pub mod rng;
+pub mod runtime;
+mod spec;
+pub mod timer;
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 /// A generic word addressable memory structure.
 // TODO: enforce word addressing
 // TODO: switch to something more efficient than a HashMap lol
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct MEM {
     mem: HashMap<u32, u32>,
 }
@@ -0,0 +1,82 @@
+/// First word of every MERL file (`beq $0, $0, 2`, which skips the header
+/// when the file is run directly).
+pub const COOKIE: u32 = 0x10000002;
+
+/// The parts of a MERL file's symbol table the loader cares about.
+/// All locations are byte offsets from the start of the file.
+#[derive(Debug, Default)]
+pub struct Merl {
+    /// Words which hold addresses, and need adjusting by the load address
+    pub relocations: Vec<u32>,
+    /// Words which should be filled in with the address of an external symbol
+    pub imports: Vec<(u32, String)>,
+}
+
+/// Parse the symbol table of a MERL file.
+/// Returns `None` if the file isn't a MERL file.
+pub fn parse(words: &[u32]) -> Option<Result<Merl, String>> {
+    if words.first() != Some(&COOKIE) {
+        return None;
+    }
+    Some(parse_table(words))
+}
+
+fn parse_table(words: &[u32]) -> Result<Merl, String> {
+    let (end_module, end_code) = match words {
+        [_, end_module, end_code, ..] => (*end_module, *end_code),
+        _ => return Err("MERL header is truncated".to_string()),
+    };
+    if end_module % 4 != 0
+        || end_code % 4 != 0
+        || end_code < 12
+        || end_code > end_module
+        || end_module as usize > words.len() * 4
+    {
+        return Err("MERL header is invalid".to_string());
+    }
+
+    let table = &words[(end_code / 4) as usize..(end_module / 4) as usize];
+    let in_code = |location: u32| {
+        if location % 4 == 0 && (12..end_code).contains(&location) {
+            Ok(location)
+        } else {
+            Err(format!(
+                "MERL entry refers to 0x{:x}, outside the code",
+                location
+            ))
+        }
+    };
+
+    let mut merl = Merl::default();
+    let mut i = 0;
+    while i < table.len() {
+        let word = |n: usize| {
+            table
+                .get(i + n)
+                .copied()
+                .ok_or_else(|| "MERL symbol table is truncated".to_string())
+        };
+        match table[i] {
+            // REL: relocation entry
+            0x01 => {
+                merl.relocations.push(in_code(word(1)?)?);
+                i += 2;
+            }
+            // ESD / ESR: external symbol definition / reference
+            format @ (0x05 | 0x11) => {
+                let location = word(1)?;
+                let len = word(2)? as usize;
+                let name = (0..len)
+                    .map(|n| word(3 + n).map(|c| (c as u8) as char))
+                    .collect::<Result<String, _>>()?;
+                if format == 0x11 {
+                    merl.imports.push((in_code(location)?, name));
+                }
+                i += 3 + len;
+            }
+            format => return Err(format!("Unknown MERL entry type 0x{:x}", format)),
+        }
+    }
+
+    Ok(merl)
+}
@@ -0,0 +1,54 @@
+use crate::cpu::Error;
+use crate::device::Device;
+use crate::mem::MEM;
+
+/// Reads return a new pseudo-random word. Writes reseed the generator.
+pub const RANDOM_ADDR: u32 = 0xffff0300;
+
+/// Memory-mapped pseudo-random number generator.
+/// Uses SplitMix64, so a given seed always produces the same sequence of
+/// numbers.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a new Rng with the given `seed`
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 32) as u32
+    }
+}
+
+impl Device for Rng {
+    fn name(&self) -> &'static str {
+        "rng"
+    }
+
+    fn maps(&self, addr: u32) -> bool {
+        addr == RANDOM_ADDR
+    }
+
+    fn peek(&self, _addr: u32) -> Option<u32> {
+        // the number the next read will return
+        Some(self.clone().next())
+    }
+
+    fn load(&mut self, _addr: u32, _mem: &mut MEM) -> Result<u32, Error> {
+        Ok(self.next())
+    }
+
+    fn store(&mut self, _addr: u32, val: u32, _mem: &mut MEM) -> Result<(), Error> {
+        self.state = val as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cpu::Error;
+
+/// `print`: prints `$1` in signed decimal, followed by a newline.
+pub const PRINT_ADDR: u32 = 0xfffe0000;
+/// `init`: sets up the heap, after the program and any data the frontend
+/// passed to it. `$1` and `$2` (the array, for `alloc.merl`) are ignored,
+/// since the `string` and `argv` frontends don't use them the same way.
+pub const INIT_ADDR: u32 = 0xfffe0004;
+/// `new`: allocates `$1` words, returning the address in `$3`, or 0 if the
+/// allocation failed.
+pub const NEW_ADDR: u32 = 0xfffe0008;
+/// `delete`: frees the allocation at `$1`.
+pub const DELETE_ADDR: u32 = 0xfffe000c;
+
+/// Space left between the end of the heap and the stack pointer at the time
+/// `init` is called.
+const STACK_RESERVE: u32 = 0x100000;
+
+/// WLP4's NULL pointer, which `delete` quietly ignores.
+const NULL: u32 = 1;
+
+/// The runtime procedures provided by the course's `print.merl` and
+/// `alloc.merl`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Procedure {
+    Print,
+    Init,
+    New,
+    Delete,
+}
+
+impl Procedure {
+    /// The procedure with the given (imported) name.
+    pub fn from_name(name: &str) -> Option<Procedure> {
+        let procedure = match name {
+            "print" => Procedure::Print,
+            "init" => Procedure::Init,
+            "new" => Procedure::New,
+            "delete" => Procedure::Delete,
+            _ => return None,
+        };
+        Some(procedure)
+    }
+
+    /// The reserved address calls to the procedure are made to.
+    pub fn addr(self) -> u32 {
+        match self {
+            Procedure::Print => PRINT_ADDR,
+            Procedure::Init => INIT_ADDR,
+            Procedure::New => NEW_ADDR,
+            Procedure::Delete => DELETE_ADDR,
+        }
+    }
+
+    /// The procedure with the given reserved address.
+    pub fn at(addr: u32) -> Option<Procedure> {
+        let procedure = match addr {
+            PRINT_ADDR => Procedure::Print,
+            INIT_ADDR => Procedure::Init,
+            NEW_ADDR => Procedure::New,
+            DELETE_ADDR => Procedure::Delete,
+            _ => return None,
+        };
+        Some(procedure)
+    }
+}
+
+/// A block of memory on the heap.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Block {
+    pub addr: u32,
+    /// Size in words
+    pub len: u32,
+}
+
+/// State for the native runtime procedures, most importantly the heap used by
+/// `new` and `delete`.
+///
+/// Heap bookkeeping is kept outside of MIPS memory, so a buggy program can't
+/// corrupt it, and misuse (double frees, freeing pointers which didn't come
+/// from `new`) can be reported precisely.
+#[derive(Clone, Debug)]
+pub struct Runtime {
+    /// First address after the loaded program and the frontend's data
+    heap_start: u32,
+    initialized: bool,
+    /// Free blocks, keyed by address
+    free: BTreeMap<u32, u32>,
+    /// Allocated blocks, keyed by address
+    allocated: BTreeMap<u32, u32>,
+    /// Addresses which have been freed, and not handed out again since
+    freed: BTreeSet<u32>,
+}
+
+impl Runtime {
+    /// Create a new Runtime, with the heap starting at `heap_start`.
+    pub fn new(heap_start: u32) -> Runtime {
+        Runtime {
+            heap_start,
+            initialized: false,
+            free: BTreeMap::new(),
+            allocated: BTreeMap::new(),
+            freed: BTreeSet::new(),
+        }
+    }
+
+    /// `init`: set up an empty heap between the end of the program (and its
+    /// data), and the stack.
+    pub fn init(&mut self, sp: u32) {
+        let start = self.heap_start;
+        let end = sp.saturating_sub(STACK_RESERVE) & !3;
+
+        self.initialized = true;
+        self.free.clear();
+        self.allocated.clear();
+        self.freed.clear();
+        if start < end {
+            self.free.insert(start, (end - start) / 4);
+        }
+    }
+
+    /// `new`: allocate `len` words using first-fit, returning the address of the
+    /// block, or 0 if there isn't enough space.
+    pub fn alloc(&mut self, len: u32) -> Result<u32, Error> {
+        if !self.initialized {
+            return Err(Error::HeapNotInitialized);
+        }
+        if len as i32 <= 0 {
+            return Ok(0);
+        }
+
+        let (addr, free_len) = match self.free.iter().find(|(_, &n)| n >= len) {
+            Some((&addr, &free_len)) => (addr, free_len),
+            None => return Ok(0),
+        };
+        self.free.remove(&addr);
+        if free_len > len {
+            self.free.insert(addr + len * 4, free_len - len);
+        }
+        self.allocated.insert(addr, len);
+        self.freed.remove(&addr);
+        Ok(addr)
+    }
+
+    /// `delete`: free the block at `addr`, merging it with any adjacent free
+    /// blocks.
+    pub fn free(&mut self, addr: u32) -> Result<(), Error> {
+        if !self.initialized {
+            return Err(Error::HeapNotInitialized);
+        }
+        if addr == NULL {
+            return Ok(());
+        }
+
+        let mut len = match self.allocated.remove(&addr) {
+            Some(len) => len,
+            None if self.freed.contains(&addr) => return Err(Error::DoubleFree(addr)),
+            None => return Err(Error::InvalidFree(addr)),
+        };
+        self.freed.insert(addr);
+
+        let mut start = addr;
+        if let Some((&prev, &prev_len)) = self.free.range(..addr).next_back() {
+            if prev + prev_len * 4 == addr {
+                self.free.remove(&prev);
+                start = prev;
+                len += prev_len;
+            }
+        }
+        if let Some(next_len) = self.free.remove(&(start + len * 4)) {
+            len += next_len;
+        }
+        self.free.insert(start, len);
+        Ok(())
+    }
+
+    /// Blocks which are still allocated.
+    pub fn leaks(&self) -> Vec<Block> {
+        self.allocated
+            .iter()
+            .map(|(&addr, &len)| Block { addr, len })
+            .collect()
+    }
+}
@@ -0,0 +1,203 @@
+//! Test specs for `uwmips test`.
+//!
+//! A spec uses the same format as `uwmips.toml`. Each `[section]` is a test
+//! case, named after the section, and settings outside of any section apply
+//! to every case. Cases set the program to run, its arguments, any `run`
+//! options, and what the program should do. Paths are relative to the spec:
+//!
+//! ```toml
+//! frontend = "twointsargs"
+//! max-steps = 10_000
+//!
+//! [sum-3]
+//! program = "recsum.mips"
+//! args = [3, 0]
+//! expect-reg = ["$3=6"]
+//! ```
+
+use std::path::Path;
+
+use crate::args::{self, ParsedArgs};
+use crate::config;
+use crate::cpu::Reg;
+use crate::frontend::{parse_i32, Opt, Registry};
+
+/// A program to run, and what it should do.
+pub struct Case {
+    pub name: String,
+    pub args: ParsedArgs,
+    pub expect: Expect,
+}
+
+/// What a program should leave behind once it finishes
+#[derive(Default)]
+pub struct Expect {
+    /// Final register values, along with how they were written in the spec
+    pub regs: Vec<(String, Reg, u32)>,
+    /// Final values of words in memory
+    pub mem: Vec<(u32, u32)>,
+    /// Everything the program should write to the console
+    pub output: Option<Vec<u8>>,
+}
+
+/// Options which take a path, which (like `program`) is relative to the spec
+const PATH_OPTIONS: &[&str] = &[
+    "input",
+    "expect-output",
+    "array-file",
+    "sandbox-dir",
+    "frame-dir",
+];
+
+/// Settings for a single case, before they're parsed into [Case]
+#[derive(Default)]
+struct Settings {
+    program: Option<String>,
+    args: Vec<String>,
+    options: Vec<args::ConfigOption>,
+    expect: Expect,
+}
+
+/// Read the test cases from the spec file at `path`, in the order they appear.
+pub fn read(path: &str, frontends: &Registry) -> Result<Vec<Case>, String> {
+    let src =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let entries = config::parse(&src).map_err(|e| format!("{} {}", path, e))?;
+    let options = args::run_options(frontends);
+    let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+
+    let mut defaults = Settings::default();
+    let mut cases: Vec<(String, Settings)> = Vec::new();
+    for entry in entries {
+        let settings = match entry.section {
+            None => &mut defaults,
+            Some(name) => {
+                if !cases.iter().any(|(case, _)| *case == name) {
+                    cases.push((name.clone(), Settings::default()));
+                }
+                &mut cases.iter_mut().find(|(case, _)| *case == name).unwrap().1
+            }
+        };
+        let line = entry.line;
+        apply(settings, &options, dir, line, &entry.key, entry.value)
+            .map_err(|e| args::in_config(path, Some(line), e))?;
+    }
+    if cases.is_empty() {
+        return Err(format!("{} doesn't have any test cases", path));
+    }
+
+    cases
+        .into_iter()
+        .map(|(name, settings)| {
+            let case = |e: String| format!("{} [{}]: {}", path, name, e);
+
+            // settings outside of any section come first, so cases can
+            // override them
+            let program = settings
+                .program
+                .or_else(|| defaults.program.clone())
+                .ok_or_else(|| case("no program given".to_string()))?;
+            let args = match settings.args.is_empty() {
+                true => defaults.args.clone(),
+                false => settings.args,
+            };
+            let mut options = defaults.options.clone();
+            options.extend(settings.options);
+
+            let cl = args::CommandLine {
+                positionals: std::iter::once(program).chain(args).collect(),
+                ..Default::default()
+            };
+            let args =
+                args::parse_run_args(path, options, cl, false, false, frontends).map_err(|e| {
+                    match e.starts_with(path) {
+                        true => e,
+                        false => case(e),
+                    }
+                })?;
+
+            let mut expect = settings.expect;
+            let mut regs = defaults.expect.regs.clone();
+            regs.append(&mut expect.regs);
+            let mut mem = defaults.expect.mem.clone();
+            mem.append(&mut expect.mem);
+            Ok(Case {
+                name,
+                args,
+                expect: Expect {
+                    regs,
+                    mem,
+                    output: expect.output.or_else(|| defaults.expect.output.clone()),
+                },
+            })
+        })
+        .collect()
+}
+
+/// Apply a single `key = value` setting to a case. Paths are relative to `dir`,
+/// the directory the spec is in.
+fn apply(
+    settings: &mut Settings,
+    options: &[&'static Opt],
+    dir: &Path,
+    line: usize,
+    key: &str,
+    value: config::Value,
+) -> Result<(), String> {
+    let items = |value: config::Value| match value {
+        config::Value::Array(items) => items.iter().map(|v| v.to_string()).collect(),
+        value => vec![value.to_string()],
+    };
+
+    match key {
+        "program" => match value {
+            config::Value::String(program) => settings.program = Some(resolve(dir, &program)),
+            _ => return Err("program should be a string".to_string()),
+        },
+        "args" => settings.args = items(value),
+        "expect-reg" => {
+            for s in items(value) {
+                let (reg, val) = args::parse_reg_assignment(&s, "expect-reg")?;
+                settings.expect.regs.push((s, reg, val));
+            }
+        }
+        "expect-mem" => {
+            for s in items(value) {
+                let (addr, val) = s
+                    .split_once('=')
+                    .ok_or_else(|| format!("expect-mem \"{}\" should be addr=val", s))?;
+                let addr = args::parse_addr(addr, "Memory address")?;
+                let val = parse_i32(val, "Memory value")? as u32;
+                settings.expect.mem.push((addr, val));
+            }
+        }
+        "expect-output-string" => match value {
+            config::Value::String(s) => settings.expect.output = Some(s.into_bytes()),
+            _ => return Err("expect-output-string should be a string".to_string()),
+        },
+        // these don't make sense without someone watching
+        "debug" | "output" | "dump-json" | "dump-mem" => {
+            return Err(format!("{} can't be used in a test", key))
+        }
+        _ => {
+            for (name, mut value) in args::config_option(options, key, key, value)? {
+                if let Some(v) = &value {
+                    if PATH_OPTIONS.contains(&name) {
+                        value = Some(resolve(dir, v));
+                    } else if name == "mem-file" {
+                        if let Some((addr, file)) = v.split_once(':') {
+                            value = Some(format!("{}:{}", addr, resolve(dir, file)));
+                        }
+                    }
+                }
+                settings.options.push((line, name, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Resolve `path` relative to `dir`, leaving absolute paths alone
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
@@ -0,0 +1,86 @@
+use crate::cpu::Error;
+use crate::device::Device;
+use crate::mem::MEM;
+
+/// Reads return the low word of the number of instructions executed so far.
+/// Also latches the high word, so that reading `COUNT_LO_ADDR` followed by
+/// `COUNT_HI_ADDR` gives a consistent 64 bit count.
+pub const COUNT_LO_ADDR: u32 = 0xffff0200;
+/// Reads return the high word latched by the last read of `COUNT_LO_ADDR`.
+pub const COUNT_HI_ADDR: u32 = 0xffff0204;
+/// Writes start a countdown of the given number of instructions (0 stops it).
+/// Reads return the number of instructions left before it expires.
+pub const COUNTDOWN_ADDR: u32 = 0xffff0208;
+/// Reads return 1 if the countdown has expired, and 0 otherwise.
+/// Writes clear the expired flag.
+pub const STATUS_ADDR: u32 = 0xffff020c;
+
+/// Memory-mapped cycle counter and countdown timer.
+/// Time is measured in executed instructions rather than wall-clock time, so
+/// programs behave the same way on every run.
+///
+/// There are no interrupts, so programs have to poll `STATUS_ADDR` to find
+/// out when the countdown expires.
+#[derive(Clone, Debug, Default)]
+pub struct Timer {
+    count: u64,
+    latched_hi: u32,
+    countdown: u32,
+    expired: bool,
+}
+
+impl Timer {
+    /// Create a new Timer, with the count starting at 0 and no countdown.
+    pub fn new() -> Timer {
+        Timer::default()
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &'static str {
+        "timer"
+    }
+
+    fn maps(&self, addr: u32) -> bool {
+        matches!(
+            addr,
+            COUNT_LO_ADDR | COUNT_HI_ADDR | COUNTDOWN_ADDR | STATUS_ADDR
+        )
+    }
+
+    fn peek(&self, addr: u32) -> Option<u32> {
+        let val = match addr {
+            COUNT_LO_ADDR => self.count as u32,
+            COUNT_HI_ADDR => self.latched_hi,
+            COUNTDOWN_ADDR => self.countdown,
+            _ => self.expired as u32,
+        };
+        Some(val)
+    }
+
+    fn load(&mut self, addr: u32, _mem: &mut MEM) -> Result<u32, Error> {
+        if addr == COUNT_LO_ADDR {
+            self.latched_hi = (self.count >> 32) as u32;
+        }
+        Ok(self.peek(addr).unwrap())
+    }
+
+    fn store(&mut self, addr: u32, val: u32, _mem: &mut MEM) -> Result<(), Error> {
+        match addr {
+            COUNTDOWN_ADDR => self.countdown = val,
+            STATUS_ADDR => self.expired = false,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.count += 1;
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            if self.countdown == 0 {
+                self.expired = true;
+            }
+        }
+    }
+}